@@ -1,85 +1,424 @@
 use std::{
     fmt::{self},
-    io::{stdin, stdout, Read},
+    fs::File,
+    io::{self, stdin, stdout, LineWriter, Read, Stdout, Write},
+    time::{Duration, Instant},
 };
 
 use crossterm::{
-    event::{read, Event, KeyCode, KeyModifiers},
+    cursor::MoveTo,
+    event::{poll, read, Event, KeyCode, KeyModifiers},
     style::{Color, Print, Stylize},
-    terminal::{disable_raw_mode, enable_raw_mode},
+    terminal::{disable_raw_mode, enable_raw_mode, Clear, ClearType},
     Command, ExecutableCommand,
 };
-use twenty_48::{solvers, Direction, GameState, Tile};
+use num_format::{Locale, ToFormattedString};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use twenty_48::{
+    bench,
+    heuristics::{self, HeuristicWeights},
+    solvers, Direction, GameState, Tile,
+};
+
+/// Whether to emit ANSI colors, off for `--ascii`/`--no-color` or when `NO_COLOR` is set
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ColorMode {
+    Color,
+    Ascii,
+}
+
+impl ColorMode {
+    /// strips the color flags out of `args`
+    fn from_args(args: &mut Vec<String>) -> ColorMode {
+        let flagged = args.iter().any(|a| a == "--ascii" || a == "--no-color");
+        args.retain(|a| a != "--ascii" && a != "--no-color");
+
+        let no_color_env = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+        if flagged || no_color_env {
+            ColorMode::Ascii
+        } else {
+            ColorMode::Color
+        }
+    }
 
-struct GsCommand<'a>(&'a GameState);
+    fn paint(self, s: String, color: Color) -> String {
+        match self {
+            ColorMode::Color => s.with(color).bold().to_string(),
+            ColorMode::Ascii => s,
+        }
+    }
+}
+
+struct GsCommand<'a>(&'a GameState, ColorMode);
 
 impl<'a> Command for GsCommand<'a> {
     fn write_ansi(&self, f: &mut impl fmt::Write) -> fmt::Result {
+        let width = cell_width(self.0);
         for row in self.0.rows().iter() {
-            print_row(f, row)?;
+            print_row(f, row, self.1, width)?;
             write!(f, "|\r\n")?;
         }
-        Ok(())
+        write!(
+            f,
+            "score: {}\r\n",
+            self.0.merge_score().to_formatted_string(&Locale::en)
+        )
     }
 }
 
-fn styled(t: &Tile) -> impl fmt::Display {
-    format!("{: ^5}", t.as_u32()).with(match t.as_u32() {
-        2 => Color::White,
-        4 => Color::Rgb {
-            r: 255,
-            g: 215,
-            b: 0,
-        }, // orange
-        8 => Color::DarkYellow,
-        16 => Color::Magenta,
-        32 => Color::Green,
-        64 => Color::Blue,
-        _ => Color::White,
-    })
+/// wide enough for the biggest tile with a space either side, and never narrower than 5
+fn cell_width(game: &GameState) -> usize {
+    let widest = game
+        .rows()
+        .iter()
+        .flatten()
+        .flatten()
+        .map(|t| t.as_u32().to_string().len())
+        .max()
+        .unwrap_or(0);
+    (widest + 2).max(5)
+}
+
+/// (text, background) for a tile, with the text picked to stand out from the background
+fn tile_colors(t: &Tile) -> (Color, Color) {
+    let (r, g, b) = t.background();
+    (contrasting_text((r, g, b)), Color::Rgb { r, g, b })
 }
 
-fn print_row(f: &mut impl fmt::Write, row: &[Option<Tile>]) -> fmt::Result {
+fn contrasting_text((r, g, b): (u8, u8, u8)) -> Color {
+    let luma = 0.299 * f64::from(r) + 0.587 * f64::from(g) + 0.114 * f64::from(b);
+    if luma > 140. {
+        Color::Black
+    } else {
+        Color::White
+    }
+}
+
+fn styled(t: &Tile, mode: ColorMode, width: usize) -> String {
+    let cell = format!("{: ^width$}", t.as_u32());
+    if mode == ColorMode::Ascii {
+        return cell;
+    }
+    let (fg, bg) = tile_colors(t);
+    cell.with(fg).on(bg).bold().to_string()
+}
+
+fn print_row(
+    f: &mut impl fmt::Write,
+    row: &[Option<Tile>],
+    mode: ColorMode,
+    width: usize,
+) -> fmt::Result {
     for tile in row.iter() {
         match tile {
-            Some(tile) => write!(f, "|{: ^5}", styled(tile))?,
-            None => write!(f, "|{: ^5}", " ")?,
+            Some(tile) => write!(f, "|{}", styled(tile, mode, width))?,
+            None => write!(f, "|{: ^width$}", " ")?,
         }
     }
     Ok(())
 }
 
-fn play_interactive() {
-    let mut game = GameState::new_from_entropy();
-    let mut prev_state = None;
+/// how full the board is, like `[########        ]  50%`
+fn fill_bar(game: &GameState) -> String {
+    let filled = 16 - game.empty_count();
+    format!(
+        "[{}{}] {: >3.0}%",
+        "#".repeat(filled),
+        " ".repeat(16 - filled),
+        game.fill_fraction() * 100.
+    )
+}
+
+/// Text and colors for the messages printed when a game ends or hits its
+/// target, overridden with `--game-over-text`, `--game-over-color`,
+/// `--reached-text` and `--reached-color`
+struct Banners {
+    game_over: String,
+    game_over_color: Color,
+    reached: String,
+    reached_color: Color,
+}
+
+impl Default for Banners {
+    fn default() -> Self {
+        Self {
+            game_over: "Game over".to_string(),
+            game_over_color: Color::Red,
+            reached: "You reached".to_string(),
+            reached_color: Color::Green,
+        }
+    }
+}
+
+impl Banners {
+    /// strips the banner flags out of `args`, keeping the default for any not given
+    fn from_args(args: &mut Vec<String>) -> Banners {
+        let color = |args: &mut Vec<String>, flag: &str, default: Color| {
+            let Some(name) = take_flag_value(args, flag) else {
+                return default;
+            };
+            Color::try_from(name.as_str()).unwrap_or_else(|()| {
+                eprintln!("{flag} needs a color name like red or dark_green, not {name:?}");
+                std::process::exit(1);
+            })
+        };
+        let defaults = Banners::default();
+        Banners {
+            game_over: take_flag_value(args, "--game-over-text").unwrap_or(defaults.game_over),
+            game_over_color: color(args, "--game-over-color", defaults.game_over_color),
+            reached: take_flag_value(args, "--reached-text").unwrap_or(defaults.reached),
+            reached_color: color(args, "--reached-color", defaults.reached_color),
+        }
+    }
+
+    fn lost(&self, game: &GameState, mode: ColorMode) -> String {
+        mode.paint(
+            format!(
+                "{} — score {}, best tile {}, {} moves",
+                self.game_over,
+                game.merge_score(),
+                game.max(),
+                game.move_count()
+            ),
+            self.game_over_color,
+        )
+    }
+
+    fn won(&self, game: &GameState, mode: ColorMode) -> String {
+        mode.paint(
+            format!("{} {}!", self.reached, game.rules().target),
+            self.reached_color,
+        )
+    }
+}
+
+/// Holds the terminal in raw mode and restores it when dropped, so an early
+/// return or a panic in the game loop doesn't leave the terminal without echo
+struct RawModeGuard {
+    restore: fn() -> io::Result<()>,
+}
+
+impl RawModeGuard {
+    fn enable() -> io::Result<Self> {
+        enable_raw_mode()?;
+        Ok(Self {
+            restore: disable_raw_mode,
+        })
+    }
+}
+
+impl Drop for RawModeGuard {
+    fn drop(&mut self) {
+        let _ = (self.restore)();
+    }
+}
+
+/// The per-move time limit for `--move-time`. The clock starts when the
+/// game first waits for a key and is reset whenever the board changes.
+struct MoveClock {
+    budget: Duration,
+    deadline: Option<Instant>,
+}
+
+impl MoveClock {
+    fn new(budget: Duration) -> Self {
+        Self {
+            budget,
+            deadline: None,
+        }
+    }
 
-    enable_raw_mode().unwrap();
+    /// when the current move runs out, starting the clock if it isn't running
+    fn deadline(&mut self, now: Instant) -> Instant {
+        *self.deadline.get_or_insert(now + self.budget)
+    }
+
+    /// a new position gets the whole budget again
+    fn reset(&mut self) {
+        self.deadline = None;
+    }
+}
+
+/// The next terminal event, or `None` if `deadline` passes first. Shows
+/// the time left on the current line meanwhile, updated ten times a second.
+fn read_before(deadline: Option<Instant>, stdout: &mut Stdout) -> io::Result<Option<Event>> {
+    let Some(deadline) = deadline else {
+        return read().map(Some);
+    };
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        stdout.execute(Print(format!("\rtime left: {:.1}s ", left.as_secs_f64())))?;
+        if left.is_zero() {
+            return Ok(None);
+        }
+        if poll(left.min(Duration::from_millis(100)))? {
+            return read().map(Some);
+        }
+    }
+}
+
+/// Finished games from one run of interactive play
+#[derive(Debug, Default, PartialEq)]
+struct SessionStats {
+    games: u32,
+    best_score: u64,
+    best_tile: u32,
+    total_score: u64,
+}
+
+impl SessionStats {
+    fn record(&mut self, game: &GameState) {
+        self.games += 1;
+        self.best_score = self.best_score.max(game.merge_score());
+        self.best_tile = self.best_tile.max(game.max());
+        self.total_score += game.merge_score();
+    }
+
+    /// mean score, `None` before any game has finished
+    fn average(&self) -> Option<u64> {
+        (self.games > 0).then(|| self.total_score / u64::from(self.games))
+    }
+
+    fn summary(&self) -> String {
+        match self.average() {
+            Some(average) => format!(
+                "this session: {} games, best score {}, best tile {}, average score {average}",
+                self.games, self.best_score, self.best_tile
+            ),
+            None => "this session: no games finished yet".to_string(),
+        }
+    }
+}
+
+/// What a key does once the game is over: `u` steps back to before the move
+/// that ended it, or stays on the game-over prompt if it can't be undone, `n`
+/// records it and starts a new one with the same rules, anything else records
+/// it and quits. True if play goes on.
+fn game_over_key(game: &mut GameState, key: KeyCode, stats: &mut SessionStats) -> bool {
+    match key {
+        KeyCode::Char('u') => {
+            game.undo();
+            true
+        }
+        KeyCode::Char('n') => {
+            stats.record(game);
+            *game = interactive_game(None).with_rules(game.rules().clone());
+            true
+        }
+        _ => {
+            stats.record(game);
+            false
+        }
+    }
+}
+
+/// With `move_time`, taking longer than that over a move plays a random one
+fn play_interactive(
+    mut game: GameState,
+    mode: ColorMode,
+    banners: &Banners,
+    move_time: Option<Duration>,
+) -> io::Result<()> {
+    let mut announced_win = game.won();
+    let mut clock = move_time.map(MoveClock::new);
+    let mut stats = SessionStats::default();
+    let mut show_stats = false;
+
+    let _raw_mode = RawModeGuard::enable()?;
 
     // let backend = CrosstermBackend::new(stdout);
     // let mut terminal = Terminal::new(backend).unwrap();
     let mut stdout = stdout();
 
     'gameloop: loop {
-        // stdout.execute(Clear(ClearType::All)).unwrap();
-        stdout.execute(GsCommand(&game)).unwrap();
-        stdout.execute(Print("\n\n")).unwrap();
+        // stdout.execute(Clear(ClearType::All))?;
+        stdout.execute(GsCommand(&game, mode))?;
+        stdout.execute(Print(format!("{}\r\n", fill_bar(&game))))?;
+        if let Some(left) = game.moves_remaining() {
+            stdout.execute(Print(format!("moves left: {left}\r\n")))?;
+        }
+        if show_stats {
+            stdout.execute(Print(format!("{}\r\n", stats.summary())))?;
+        }
+        stdout.execute(Print("\n\n"))?;
 
-        if game.lost() {
-            break 'gameloop;
+        if !announced_win && game.won() {
+            announced_win = true;
+            stdout.execute(Print(format!("{}\r\n\n", banners.won(&game, mode))))?;
         }
 
-        let dir = match read().unwrap() {
+        if game.finished() {
+            if !(game.rules().win_stops_game && game.won()) {
+                stdout.execute(Print(format!("{}\r\n", banners.lost(&game, mode))))?;
+            }
+            if let Some(seed) = game.seed() {
+                stdout.execute(Print(format!(
+                    "seed {seed}, play it again with `i --seed {seed}`\r\n"
+                )))?;
+            }
+            let undo = if game.can_undo() && game.undos_remaining() != Some(0) {
+                "u to undo the last move, "
+            } else {
+                ""
+            };
+            stdout.execute(Print(format!(
+                "{undo}n for a new game, any other key to quit\r\n"
+            )))?;
+            let code = match read()? {
+                Event::Key(k) if k.modifiers == KeyModifiers::NONE => k.code,
+                _ => KeyCode::Esc,
+            };
+            if !game_over_key(&mut game, code, &mut stats) {
+                if show_stats {
+                    stdout.execute(Print(format!("{}\r\n", stats.summary())))?;
+                }
+                break 'gameloop;
+            }
+            announced_win = game.won();
+            if let Some(clock) = &mut clock {
+                clock.reset();
+            }
+            continue 'gameloop;
+        }
+
+        let deadline = clock.as_mut().map(|c| c.deadline(Instant::now()));
+        let Some(event) = read_before(deadline, &mut stdout)? else {
+            // a game that isn't finished always has a legal move
+            let dir = solvers::solver_random(&game).unwrap();
+            stdout.execute(Print(format!("\r\nout of time, played {dir:?}\r\n")))?;
+            game.apply(dir);
+            if let Some(clock) = &mut clock {
+                clock.reset();
+            }
+            continue 'gameloop;
+        };
+        let dir = match event {
             Event::Key(k) => match (k.code, k.modifiers) {
                 (KeyCode::Left, KeyModifiers::NONE) => Direction::Left,
                 (KeyCode::Right, KeyModifiers::NONE) => Direction::Right,
                 (KeyCode::Up, KeyModifiers::NONE) => Direction::Up,
                 (KeyCode::Down, KeyModifiers::NONE) => Direction::Down,
                 (KeyCode::Char('u'), KeyModifiers::NONE) => {
-                    if let Some(prev) = prev_state.take() {
-                        game = prev;
+                    if game.undo() {
+                        if let Some(clock) = &mut clock {
+                            clock.reset();
+                        }
+                    }
+                    continue 'gameloop;
+                }
+                (KeyCode::Char('r'), KeyModifiers::NONE) => {
+                    if game.restart() {
+                        announced_win = game.won();
+                        if let Some(clock) = &mut clock {
+                            clock.reset();
+                        }
                     }
                     continue 'gameloop;
                 }
+                (KeyCode::Char('s'), KeyModifiers::NONE) => {
+                    show_stats = !show_stats;
+                    continue 'gameloop;
+                }
                 (KeyCode::Char('c'), KeyModifiers::CONTROL) => break 'gameloop,
                 _ => {
                     println!("{:?}", k);
@@ -92,40 +431,607 @@ fn play_interactive() {
             }
         };
 
-        if game.can_move(dir) {
-            prev_state = Some(game.clone());
-            game.do_move(dir);
-            game.spawn_tile_with_dir(dir);
+        if game.apply(dir) {
+            if let Some(clock) = &mut clock {
+                clock.reset();
+            }
         }
     }
 
-    disable_raw_mode().unwrap();
+    Ok(())
 }
 
-fn solve(solver: fn(&mut GameState)) {
+/// Writes a `<compact board> <score>` line per frame, flushed line by line so
+/// an interrupted run still leaves a usable file. Games are separated by a
+/// blank line.
+struct Recorder(LineWriter<File>);
+
+impl Recorder {
+    fn create(path: &str) -> io::Result<Self> {
+        Ok(Self(LineWriter::new(File::create(path)?)))
+    }
+
+    fn frame(&mut self, game: &GameState) {
+        if let Err(e) = writeln!(self.0, "{} {}", game.to_compact(), game.merge_score()) {
+            eprintln!("failed to record frame: {e}");
+            std::process::exit(1);
+        }
+    }
+
+    fn end_game(&mut self) {
+        if let Err(e) = writeln!(self.0) {
+            eprintln!("failed to record frame: {e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Lets through at most one frame per interval, so a fast solver is drawn
+/// at a watchable rate. Frames in between are dropped, not delayed.
+struct FrameLimiter {
+    interval: Duration,
+    last: Option<Instant>,
+}
+
+impl FrameLimiter {
+    fn new(fps: u32) -> Self {
+        Self {
+            interval: Duration::from_secs(1) / fps,
+            last: None,
+        }
+    }
+
+    /// whether a frame at `now` should be drawn
+    fn ready(&mut self, now: Instant) -> bool {
+        if self.last.is_some_and(|last| now < last + self.interval) {
+            return false;
+        }
+        self.last = Some(now);
+        true
+    }
+}
+
+/// redraw `game` over whatever is on screen
+fn draw_frame(game: &GameState, mode: ColorMode) {
+    let mut stdout = stdout();
+    stdout
+        .execute(Clear(ClearType::All))
+        .and_then(|s| s.execute(MoveTo(0, 0)))
+        .and_then(|s| s.execute(GsCommand(game, mode)))
+        .unwrap();
+}
+
+/// With `fps`, the games are drawn as they're played, at most that many boards a second
+fn solve(
+    solver: solvers::Solver,
+    mut recorder: Option<Recorder>,
+    fps: Option<u32>,
+    mode: ColorMode,
+) {
+    let mut limiter = fps.map(FrameLimiter::new);
     let mut scores = Vec::new();
     loop {
         let mut game = GameState::new_from_entropy();
 
-        solver(&mut game);
+        if let Some(rec) = &mut recorder {
+            rec.frame(&game);
+        }
+        solvers::play_observed(&mut game, solver, |g| {
+            if let Some(rec) = &mut recorder {
+                rec.frame(g);
+            }
+            if limiter.as_mut().is_some_and(|l| l.ready(Instant::now())) {
+                draw_frame(g, mode);
+            }
+        });
+        if let Some(rec) = &mut recorder {
+            rec.end_game();
+        }
         scores.push(game.max());
+        scores.sort();
 
-        game.print();
-        println!(
-            "{}",
-            2.0_f64
-                .powf(scores.iter().map(|i| i.ilog(2)).sum::<u32>() as f64 / scores.len() as f64)
-        );
+        // however many frames were dropped, the final board is always shown
+        match limiter {
+            Some(_) => draw_frame(&game, mode),
+            None => game.print(),
+        }
+        println!("median max tile: {}", scores[scores.len() / 2]);
         let _ = stdin().read(&mut [0; 1024]).unwrap(); // single read just to wait for input
     }
 }
 
+/// With `jsonl`, each game is printed as a JSON object on its own line the
+/// moment it finishes, flushed so a reader sees whole lines even if the run
+/// is cut short. Otherwise a table is printed once every game is done.
+fn bench(solver: solvers::Solver, games: u64, first_seed: u64, jsonl: bool) {
+    if jsonl {
+        bench::run_streaming(
+            games,
+            first_seed,
+            |gs| {
+                solvers::play(gs, solver);
+            },
+            |r| {
+                let mut out = stdout().lock();
+                let line = serde_json::to_string(r).unwrap();
+                // the reader went away, so nobody wants the rest
+                if writeln!(out, "{line}").and_then(|_| out.flush()).is_err() {
+                    std::process::exit(0);
+                }
+            },
+        );
+        return;
+    }
+    let results = bench::run(solver, games, first_seed);
+
+    println!("seed\tmax_tile\tmerge_score\tmoves");
+    for r in &results {
+        println!("{}\t{}\t{}\t{}", r.seed, r.max_tile, r.merge_score, r.moves);
+    }
+}
+
+/// one-shot heuristic report for a position, for tuning solvers
+fn analyze(game: &GameState) -> String {
+    let legal: Vec<String> = Direction::all()
+        .map(|d| match game.can_move(d) {
+            true => format!("{d:?}"),
+            false => ".".into(),
+        })
+        .collect();
+    let gains: Vec<String> = Direction::all()
+        .map(|d| match game.score_if_moved(d) {
+            Some(gain) => format!("{d:?} +{gain}"),
+            None => format!("{d:?} -"),
+        })
+        .collect();
+
+    format!(
+        "{game}\n\
+         max tile:     {}\n\
+         empty cells:  {}\n\
+         monotonicity: {}\n\
+         smoothness:   {}\n\
+         legal moves:  {}\n\
+         merge gains:  {}\n",
+        game.max(),
+        game.empty_count(),
+        heuristics::monotonicity(game),
+        heuristics::smoothness(game),
+        legal.join(" "),
+        gains.join(", "),
+    )
+}
+
+/// Random search over `HeuristicWeights`: each of `samples` weight sets plays
+/// `games` seeded games with `solver_weighted`, and the best mean merge score wins
+fn tune(samples: u64, games: u64, seed: u64) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut best: Option<(f64, HeuristicWeights)> = None;
+    for sample in 0..samples {
+        let weights = HeuristicWeights {
+            corner: rng.gen_range(0.0..2.0),
+            empty: rng.gen_range(0.0..32.0),
+            monotonicity: rng.gen_range(0.0..8.0),
+            smoothness: rng.gen_range(0.0..4.0),
+        };
+        let results = bench::run_parallel(games, 0, |gs| {
+            gs.apply_all_then_report(|g| solvers::solver_weighted(g, &weights));
+        });
+        let mean = results.iter().map(|r| r.merge_score as f64).sum::<f64>() / games as f64;
+        println!("sample {sample}: mean score {mean:.0} with {weights:?}");
+
+        if best.is_none_or(|(b, _)| mean > b) {
+            best = Some((mean, weights));
+        }
+    }
+    if let Some((mean, weights)) = best {
+        println!("best mean score {mean:.0}:\n{weights:?}");
+    }
+}
+
+/// A new interactive game, from `seed` to replay one or a fresh seed
+/// otherwise. Either way the seed is kept so it can be shown at the end.
+fn interactive_game(seed: Option<u64>) -> GameState {
+    seed.map_or_else(GameState::new_from_entropy, GameState::new_from_seed)
+}
+
+fn run_interactive(
+    game: GameState,
+    mode: ColorMode,
+    banners: &Banners,
+    move_time: Option<Duration>,
+) {
+    if let Err(e) = play_interactive(game, mode, banners, move_time) {
+        eprintln!("terminal error: {e}");
+        std::process::exit(1);
+    }
+}
+
+/// run two solvers on identically seeded games and print the results next to each other
+fn compare(a: (&str, solvers::Solver), b: (&str, solvers::Solver), seed: u64) {
+    let play = |solver: solvers::Solver| {
+        let mut game = GameState::new_from_seed(seed);
+        solvers::play(&mut game, solver);
+        game
+    };
+    let (game_a, game_b) = (play(a.1), play(b.1));
+
+    println!("{: <30}{}", a.0, b.0);
+    for (row_a, row_b) in game_a.to_string().lines().zip(game_b.to_string().lines()) {
+        println!("{row_a: <30}{row_b}");
+    }
+    for (label, f) in [
+        ("score", GameState::merge_score as fn(&GameState) -> u64),
+        ("moves", GameState::move_count),
+        ("max tile", |g| u64::from(g.max())),
+    ] {
+        let left = format!("{label}: {}", f(&game_a));
+        println!("{left: <30}{label}: {}", f(&game_b));
+    }
+}
+
+fn lookup_solver(name: Option<&String>) -> solvers::Solver {
+    let registry = solvers::registry();
+    match name.and_then(|n| registry.get(n.as_str())) {
+        Some(s) => *s,
+        None => {
+            eprintln!(
+                "unrecognized solver {name:?}, available: {}",
+                registry.keys().copied().collect::<Vec<_>>().join(", ")
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+/// `--seed`'s value, taken out of `args`
+fn take_seed(args: &mut Vec<String>) -> Option<u64> {
+    take_flag_value(args, "--seed").map(|seed| {
+        seed.parse().unwrap_or_else(|_| {
+            eprintln!("--seed needs a number");
+            std::process::exit(1);
+        })
+    })
+}
+
+/// the number at `args[i]`, `default` if it's missing; anything else exits
+/// with a usage error naming it `name`
+fn number_arg(args: &[String], i: usize, name: &str, default: u64) -> u64 {
+    match args.get(i) {
+        None => default,
+        Some(arg) => arg.parse().unwrap_or_else(|_| {
+            eprintln!("{name} needs a whole number, not {arg:?}");
+            std::process::exit(1);
+        }),
+    }
+}
+
+/// removes `flag` and the argument after it from `args`, returning that argument
+fn take_flag_value(args: &mut Vec<String>, flag: &str) -> Option<String> {
+    let i = args.iter().position(|a| a == flag)?;
+    let value = args.get(i + 1).cloned();
+    args.drain(i..(i + 2).min(args.len()));
+    value.or_else(|| {
+        eprintln!("{flag} needs a value");
+        std::process::exit(1);
+    })
+}
+
 fn main() {
-    let arg = std::env::args().nth(1).unwrap();
+    let mut args: Vec<String> = std::env::args().collect();
+    let mode = ColorMode::from_args(&mut args);
+    let banners = Banners::from_args(&mut args);
+    let recorder = take_flag_value(&mut args, "--record").map(|path| {
+        Recorder::create(&path).unwrap_or_else(|e| {
+            eprintln!("can't record to {path}: {e}");
+            std::process::exit(1);
+        })
+    });
+    let fps = take_flag_value(&mut args, "--frames-per-second").map(|fps| match fps.parse() {
+        Ok(fps) if fps > 0 => fps,
+        _ => {
+            eprintln!("--frames-per-second needs a whole number above 0, not {fps:?}");
+            std::process::exit(1);
+        }
+    });
+    let move_time = take_flag_value(&mut args, "--move-time").map(|ms| {
+        Duration::from_millis(ms.parse().unwrap_or_else(|_| {
+            eprintln!("--move-time needs a number of milliseconds");
+            std::process::exit(1);
+        }))
+    });
+    let arg = args.get(1).unwrap();
     match arg.as_str() {
-        "i" | "interactive" => play_interactive(),
-        "urld" => solve(solvers::solver_up_right_left_down),
-        "snake" => solve(solvers::solver_snake),
-        c => panic!("unrecognized command {c}"),
+        "i" | "interactive" => run_interactive(
+            interactive_game(take_seed(&mut args)),
+            mode,
+            &banners,
+            move_time,
+        ),
+        "code" => run_interactive(
+            GameState::new_from_code(&args[2..].join(" ")),
+            mode,
+            &banners,
+            move_time,
+        ),
+        "play-from" => match args[2..].join(" ").parse() {
+            Ok(game) => run_interactive(game, mode, &banners, move_time),
+            Err(e) => {
+                eprintln!("invalid board: {e}");
+                eprintln!("expected 16 cells like \"2,4,.,8/16,.,.,2/.,.,.,./.,.,.,.\"");
+                std::process::exit(1);
+            }
+        },
+        // a parsed board has no history, so its merge score isn't known and isn't shown
+        "analyze" => match args[2..].join(" ").parse::<GameState>() {
+            Ok(game) => print!("{}", analyze(&game)),
+            Err(e) => {
+                eprintln!("invalid board: {e}");
+                eprintln!("expected 16 cells like \"2,4,.,8/16,.,.,2/.,.,.,./.,.,.,.\"");
+                std::process::exit(1);
+            }
+        },
+        "list" => {
+            for name in solvers::registry().keys() {
+                println!("{name}");
+            }
+        }
+        "compare" => {
+            let seed = take_seed(&mut args).unwrap_or(0);
+            let a = lookup_solver(args.get(2));
+            let b = lookup_solver(args.get(3));
+            compare((&args[2], a), (&args[3], b), seed);
+        }
+        "tune" => {
+            let samples = number_arg(&args, 2, "samples", 20);
+            let games = number_arg(&args, 3, "games", 20);
+            let seed = number_arg(&args, 4, "seed", 0);
+            tune(samples, games, seed);
+        }
+        "bench" => {
+            let jsonl = args.iter().any(|a| a == "--jsonl");
+            args.retain(|a| a != "--jsonl");
+            let solver = lookup_solver(args.get(2));
+            let games = number_arg(&args, 3, "games", 100);
+            let seed = number_arg(&args, 4, "seed", 0);
+            bench(solver, games, seed, jsonl);
+        }
+        c => match solvers::registry().get(c) {
+            Some(solver) => solve(*solver, recorder, fps, mode),
+            None => panic!("unrecognized command {c}"),
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io,
+        panic::catch_unwind,
+        sync::atomic::{AtomicBool, Ordering},
+        time::{Duration, Instant},
+    };
+
+    use twenty_48::GameState;
+
+    use crossterm::{event::KeyCode, style::Color};
+
+    use crate::{
+        analyze, cell_width, contrasting_text, game_over_key, interactive_game, number_arg,
+        print_row, take_flag_value, take_seed, tile_colors, Banners, ColorMode, FrameLimiter,
+        MoveClock, RawModeGuard, SessionStats,
+    };
+
+    #[test]
+    fn raw_mode_guard_restores_on_panic() {
+        static RESTORED: AtomicBool = AtomicBool::new(false);
+        fn restore() -> io::Result<()> {
+            RESTORED.store(true, Ordering::SeqCst);
+            Ok(())
+        }
+
+        let result = catch_unwind(|| {
+            let _guard = RawModeGuard { restore };
+            panic!("game loop blew up");
+        });
+        assert!(result.is_err());
+        assert!(RESTORED.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn wide_tiles_fit() {
+        let game = GameState::from_list([
+            65536, 2, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1,
+        ]);
+        let width = cell_width(&game);
+        assert_eq!(width, 7);
+
+        let mut row = String::new();
+        print_row(&mut row, &game.rows()[0], ColorMode::Ascii, width).unwrap();
+        assert_eq!(row, "| 65536 |   2   |       |       ");
+
+        assert_eq!(cell_width(&GameState::from_list([2; 16])), 5);
+    }
+
+    #[test]
+    fn tile_text_contrasts_with_background() {
+        assert_eq!(contrasting_text((255, 255, 255)), Color::Black);
+        assert_eq!(contrasting_text((0x14, 0x40, 0x6b)), Color::White);
+
+        // tiles past the end of the palette still get colors of their own
+        let big = GameState::from_list([
+            131072, 65536, 16384, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1,
+        ]);
+        let colors: Vec<_> = big.rows()[0].iter().flatten().map(tile_colors).collect();
+        assert!(colors.windows(2).all(|w| w[0] != w[1]));
+    }
+
+    #[test]
+    fn move_clock_resets_each_move() {
+        let mut clock = MoveClock::new(Duration::from_millis(500));
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        assert_eq!(clock.deadline(at(0)), at(500));
+        // redraws and ignored keys don't buy more time
+        assert_eq!(clock.deadline(at(300)), at(500));
+        clock.reset();
+        assert_eq!(clock.deadline(at(400)), at(900));
+    }
+
+    #[test]
+    fn undo_after_losing() {
+        let mut game = GameState::new_from_seed(8);
+        while !game.lost() {
+            game.apply(twenty_48::solvers::solver_random(&game).unwrap());
+        }
+        let lost = game.clone();
+        let mut stats = SessionStats::default();
+
+        assert!(game_over_key(&mut game, KeyCode::Char('u'), &mut stats));
+        assert!(!game.lost());
+        assert!(!game.valid_moves().is_empty());
+        assert_eq!(game.move_count() + 1, lost.move_count());
+        // stepping back isn't the end of a game
+        assert_eq!(stats.games, 0);
+
+        let mut game = lost.clone();
+        assert!(game_over_key(&mut game, KeyCode::Char('n'), &mut stats));
+        assert_eq!(game.move_count(), 0);
+        assert_eq!(stats.games, 1);
+
+        let mut game = lost.clone();
+        assert!(!game_over_key(&mut game, KeyCode::Char('q'), &mut stats));
+        assert_eq!(game, lost);
+        assert_eq!(stats.games, 2);
+
+        // nothing to undo keeps the game over, and quitting still counts it
+        let mut stuck = GameState::from_list([2, 4, 2, 4, 4, 2, 4, 2, 2, 4, 2, 4, 4, 2, 4, 2]);
+        assert!(stuck.lost());
+        assert!(game_over_key(&mut stuck, KeyCode::Char('u'), &mut stats));
+        assert!(stuck.lost());
+        assert_eq!(stats.games, 2);
+        assert!(!game_over_key(&mut stuck, KeyCode::Char('q'), &mut stats));
+        assert_eq!(stats.games, 3);
+    }
+
+    #[test]
+    fn session_stats_add_up() {
+        let mut stats = SessionStats::default();
+        assert_eq!(stats.average(), None);
+        assert_eq!(stats.summary(), "this session: no games finished yet");
+
+        // scores are merge scores, the same as the status line shows
+        let games = [3, 4, 5].map(|seed| {
+            let mut game = GameState::new_from_seed(seed);
+            twenty_48::solvers::play(&mut game, twenty_48::solvers::solver_random);
+            stats.record(&game);
+            game
+        });
+        let best_score = games.iter().map(GameState::merge_score).max().unwrap();
+        let best_tile = games.iter().map(GameState::max).max().unwrap();
+        let expected = games.iter().map(GameState::merge_score).sum::<u64>() / 3;
+        assert!(best_score > 0);
+        assert_eq!(stats.games, 3);
+        assert_eq!(stats.best_score, best_score);
+        assert_eq!(stats.best_tile, best_tile);
+        assert_eq!(stats.average(), Some(expected));
+        assert_eq!(
+            stats.summary(),
+            format!(
+                "this session: 3 games, best score {best_score}, best tile {best_tile}, average score {expected}"
+            )
+        );
+    }
+
+    #[test]
+    fn frames_are_dropped_not_delayed() {
+        let mut limiter = FrameLimiter::new(10);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        assert!(limiter.ready(at(0)));
+        assert!(!limiter.ready(at(10)));
+        assert!(!limiter.ready(at(99)));
+        assert!(limiter.ready(at(100)));
+        // the next frame is timed from the last one drawn, not the last one offered
+        assert!(!limiter.ready(at(150)));
+        assert!(limiter.ready(at(250)));
+    }
+
+    #[test]
+    fn same_seed_same_interactive_game() {
+        let mut args: Vec<String> = ["2048", "i", "--seed", "1234"].map(String::from).into();
+        let seed = take_seed(&mut args);
+        assert_eq!(args, ["2048", "i"]);
+        assert_eq!(seed, Some(1234));
+
+        let (a, b) = (interactive_game(seed), interactive_game(seed));
+        assert_eq!(a, b);
+        assert_eq!(a.seed(), Some(1234));
+
+        // a fresh game still has a seed to print when it's over
+        let fresh = interactive_game(None);
+        assert_eq!(interactive_game(fresh.seed()), fresh);
+    }
+
+    #[test]
+    fn flag_values_are_taken_out() {
+        let mut args: Vec<String> = ["2048", "snake", "--record", "out.txt", "--ascii"]
+            .map(String::from)
+            .into();
+        assert_eq!(
+            take_flag_value(&mut args, "--record").as_deref(),
+            Some("out.txt")
+        );
+        assert_eq!(args, ["2048", "snake", "--ascii"]);
+        assert_eq!(take_flag_value(&mut args, "--record"), None);
+    }
+
+    #[test]
+    fn banners_from_flags() {
+        let mut args: Vec<String> = [
+            "2048",
+            "i",
+            "--game-over-text",
+            "Out of moves",
+            "--reached-color",
+            "dark_cyan",
+        ]
+        .map(String::from)
+        .into();
+        let banners = Banners::from_args(&mut args);
+        assert_eq!(args, ["2048", "i"]);
+        assert_eq!(banners.game_over, "Out of moves");
+        assert_eq!(banners.reached_color, Color::DarkCyan);
+        // the rest keep their defaults
+        assert_eq!(banners.game_over_color, Banners::default().game_over_color);
+        assert_eq!(banners.reached, "You reached");
+
+        let game = GameState::from_list([2, 4, 2, 4, 4, 2, 4, 2, 2, 4, 2, 4, 4, 2, 4, 2]);
+        assert_eq!(
+            banners.lost(&game, ColorMode::Ascii),
+            "Out of moves — score 0, best tile 4, 0 moves"
+        );
+    }
+
+    #[test]
+    fn number_args_default_when_missing() {
+        let args: Vec<String> = ["2048", "tune", "5"].map(String::from).into();
+        assert_eq!(number_arg(&args, 2, "samples", 20), 5);
+        assert_eq!(number_arg(&args, 3, "games", 20), 20);
+    }
+
+    #[test]
+    fn analyze_reports_moves() {
+        let game: GameState = "2,2,.,./.,.,.,./.,.,.,./.,.,.,.".parse().unwrap();
+        let report = analyze(&game);
+        assert!(report.contains("empty cells:  14\n"), "{report}");
+        assert!(
+            report.contains("legal moves:  . Down Left Right\n"),
+            "{report}"
+        );
+        assert!(
+            report.contains("merge gains:  Up -, Down +0, Left +4, Right +4\n"),
+            "{report}"
+        );
     }
 }
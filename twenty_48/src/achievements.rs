@@ -0,0 +1,117 @@
+use std::collections::BTreeSet;
+
+use crate::{Direction, GameState};
+
+/// tiles whose first appearance is worth celebrating
+pub const MILESTONE_TILES: [u32; 4] = [512, 1024, 2048, 4096];
+
+/// Lifetime milestones across every game played, fed one move at a time
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Achievements {
+    /// which of `MILESTONE_TILES` have been reached
+    #[serde(default)]
+    tiles: BTreeSet<u32>,
+    #[serde(default)]
+    total_merges: u64,
+    /// most moves in one game
+    #[serde(default)]
+    longest_game: u64,
+    /// most merge score gained by a single move
+    #[serde(default)]
+    best_move_score: u64,
+}
+
+impl Achievements {
+    /// Count the move `dir` that took `before` to `after`, returning the
+    /// milestone tiles it reached for the first time ever. Undoing and
+    /// replaying a move counts it again, but never unlocks anything twice.
+    pub fn record_move(
+        &mut self,
+        before: &GameState,
+        dir: Direction,
+        after: &GameState,
+    ) -> Vec<u32> {
+        self.total_merges += before.merge_count(dir) as u64;
+        self.best_move_score = self
+            .best_move_score
+            .max(after.merge_score().saturating_sub(before.merge_score()));
+        self.longest_game = self.longest_game.max(after.move_count());
+
+        let max = after.max();
+        MILESTONE_TILES
+            .into_iter()
+            .filter(|&t| t <= max && self.tiles.insert(t))
+            .collect()
+    }
+
+    pub fn reached(&self, tile: u32) -> bool {
+        self.tiles.contains(&tile)
+    }
+
+    pub fn total_merges(&self) -> u64 {
+        self.total_merges
+    }
+
+    pub fn longest_game(&self) -> u64 {
+        self.longest_game
+    }
+
+    pub fn best_move_score(&self) -> u64 {
+        self.best_move_score
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Achievements;
+    use crate::{Direction, GameState};
+
+    #[test]
+    fn milestones_unlock_once() {
+        let mut achievements = Achievements::default();
+        let gs = GameState::from_list_seeded(
+            [
+                256, 256, 4, 4, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1,
+            ],
+            9,
+        );
+        let mut after = gs.clone();
+        assert!(after.apply(Direction::Left));
+        assert_eq!(
+            achievements.record_move(&gs, Direction::Left, &after),
+            [512]
+        );
+        assert!(achievements.reached(512));
+        assert!(!achievements.reached(1024));
+        assert_eq!(achievements.total_merges(), 2);
+        assert_eq!(achievements.best_move_score(), 520);
+        assert_eq!(achievements.longest_game(), 1);
+
+        // the same crossing again, as after an undo or in a later game
+        assert!(achievements
+            .record_move(&gs, Direction::Left, &after)
+            .is_empty());
+        assert_eq!(achievements.total_merges(), 4);
+        assert_eq!(achievements.best_move_score(), 520);
+
+        // a tile past several milestones unlocks all of them
+        let big = GameState::from_list_seeded(
+            [
+                2048, 2048, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1,
+            ],
+            9,
+        );
+        let mut after = big.clone();
+        assert!(after.apply(Direction::Right));
+        assert_eq!(
+            achievements.record_move(&big, Direction::Right, &after),
+            [1024, 2048, 4096]
+        );
+
+        let json = serde_json::to_string(&achievements).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Achievements>(&json).unwrap(),
+            achievements
+        );
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    solvers::{play, Solver},
+    GameState,
+};
+
+/// Raw outcome of one benchmarked game, left for the caller to aggregate
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct GameResult {
+    pub seed: u64,
+    pub max_tile: u32,
+    pub merge_score: u64,
+    pub moves: u64,
+}
+
+impl GameResult {
+    fn new(seed: u64, gs: &GameState) -> Self {
+        Self {
+            seed,
+            max_tile: gs.max(),
+            merge_score: gs.merge_score(),
+            moves: gs.move_count(),
+        }
+    }
+}
+
+/// Play `games` games to completion with `solver`, seeded `first_seed`, `first_seed + 1`, ...
+pub fn run(solver: Solver, games: u64, first_seed: u64) -> Vec<GameResult> {
+    run_parallel(games, first_seed, |gs| {
+        play(gs, solver);
+    })
+}
+
+/// Like `run`, but each game is played by calling `play_game` on it, so the
+/// player can carry state like heuristic weights. Games are split across
+/// every available core, and results still come back in seed order.
+pub fn run_parallel(
+    games: u64,
+    first_seed: u64,
+    play_game: impl Fn(&mut GameState) + Sync,
+) -> Vec<GameResult> {
+    run_streaming(games, first_seed, play_game, |_| {})
+}
+
+/// `run_parallel`, also handing each result to `on_result` the moment its
+/// game finishes, on whichever thread played it. That's completion order,
+/// not seed order; the returned results are still in seed order.
+pub fn run_streaming(
+    games: u64,
+    first_seed: u64,
+    play_game: impl Fn(&mut GameState) + Sync,
+    on_result: impl Fn(&GameResult) + Sync,
+) -> Vec<GameResult> {
+    let seeds: Vec<u64> = (first_seed..first_seed + games).collect();
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let per_thread = seeds.len().div_ceil(threads).max(1);
+    let play_game = &play_game;
+    let on_result = &on_result;
+
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = seeds
+            .chunks(per_thread)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|&seed| {
+                            let mut gs = GameState::new_from_seed(seed);
+                            play_game(&mut gs);
+                            let result = GameResult::new(seed, &gs);
+                            on_result(&result);
+                            result
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect()
+    })
+}
+
+/// How far `solver` gets from `gs` as it stands: plays on from the same
+/// position `runs` times, reseeded `0..runs`, and counts how often each max
+/// tile was the final one.
+pub fn probe(gs: &GameState, solver: Solver, runs: u64) -> BTreeMap<u32, u64> {
+    let mut maxes = BTreeMap::new();
+    for seed in 0..runs {
+        let mut game = gs.clone();
+        game.reseed(seed);
+        play(&mut game, solver);
+        *maxes.entry(game.max()).or_insert(0) += 1;
+    }
+    maxes
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{heuristics::HeuristicWeights, solvers, GameState};
+
+    #[test]
+    fn one_result_per_game() {
+        let results = super::run(solvers::solver_up_right_left_down, 5, 100);
+        assert_eq!(results.len(), 5);
+        assert_eq!(
+            results.iter().map(|r| r.seed).collect::<Vec<_>>(),
+            [100, 101, 102, 103, 104]
+        );
+    }
+
+    #[test]
+    fn parallel_matches_sequential() {
+        let weights = HeuristicWeights {
+            smoothness: 2.,
+            ..HeuristicWeights::default()
+        };
+        let results = super::run_parallel(9, 40, |gs| {
+            gs.apply_all_then_report(|g| solvers::solver_weighted(g, &weights));
+        });
+
+        let sequential: Vec<_> = (40..49)
+            .map(|seed| {
+                let mut gs = GameState::new_from_seed(seed);
+                gs.apply_all_then_report(|g| solvers::solver_weighted(g, &weights));
+                super::GameResult::new(seed, &gs)
+            })
+            .collect();
+        assert_eq!(results, sequential);
+    }
+
+    #[test]
+    fn streamed_results_match_returned() {
+        let streamed = std::sync::Mutex::new(Vec::new());
+        let results = super::run_streaming(
+            12,
+            7,
+            |gs| {
+                solvers::play(gs, solvers::solver_snake);
+            },
+            |r| streamed.lock().unwrap().push(r.clone()),
+        );
+        let mut streamed = streamed.into_inner().unwrap();
+        streamed.sort_by_key(|r| r.seed);
+        assert_eq!(streamed, results);
+    }
+
+    #[test]
+    fn probe_continues_from_position() {
+        let gs = GameState::from_list([128, 64, 32, 8, 8, 4, 8, 4, -1, -1, -1, -1, -1, -1, -1, -1]);
+        let maxes = super::probe(&gs, solvers::solver_up_right_left_down, 20);
+        assert_eq!(maxes.values().sum::<u64>(), 20);
+        assert!(maxes.keys().all(|&m| m >= 128));
+        assert_eq!(
+            maxes,
+            super::probe(&gs, solvers::solver_up_right_left_down, 20)
+        );
+        assert_eq!(gs.move_count(), 0, "probing shouldn't touch the original");
+    }
+}
@@ -0,0 +1,278 @@
+use crate::{Direction, GameState, MoveEffects, Rules, Tile};
+
+/// One thing that happened to the board
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum LogEntry {
+    Spawn {
+        index: usize,
+        tile: Tile,
+    },
+    Move(Direction),
+    /// a tile removed by zen mode's `GameState::unstick`
+    Clear {
+        index: usize,
+    },
+}
+
+/// Append-only record of a game. Spawns are stored explicitly rather than
+/// re-derived from the seed, so replaying doesn't depend on how the RNG
+/// turns seeds into tiles. The rules are kept too, since they change what
+/// a move does.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct GameLog {
+    seed: u64,
+    /// logs from before rules were kept replay under the default ones
+    #[serde(default)]
+    rules: Rules,
+    actions: Vec<LogEntry>,
+}
+
+impl GameLog {
+    /// Start logging `gs`, recording the tiles already on the board as spawns.
+    /// Boards without a known seed are logged with seed 0.
+    pub fn new(gs: &GameState) -> Self {
+        let actions = gs
+            .rows()
+            .iter()
+            .flatten()
+            .enumerate()
+            .filter_map(|(index, t)| t.map(|tile| LogEntry::Spawn { index, tile }))
+            .collect();
+        Self {
+            seed: gs.seed().unwrap_or(0),
+            rules: gs.rules().clone(),
+            actions,
+        }
+    }
+
+    /// `GameState::apply`, recording the move, the tiles it spawned and any
+    /// zen mode cleared
+    pub fn apply(&mut self, gs: &mut GameState, dir: Direction) -> bool {
+        self.apply_spawning(gs, dir).is_some()
+    }
+
+    /// `GameState::apply_spawning`, logging the move as `apply` does
+    pub fn apply_spawning(
+        &mut self,
+        gs: &mut GameState,
+        dir: Direction,
+    ) -> Option<Vec<(usize, Tile)>> {
+        let MoveEffects { spawned, cleared } = gs.apply_clearing(dir)?;
+        self.actions.push(LogEntry::Move(dir));
+        self.actions.extend(
+            spawned
+                .iter()
+                .map(|&(index, tile)| LogEntry::Spawn { index, tile }),
+        );
+        self.actions
+            .extend(cleared.into_iter().map(|index| LogEntry::Clear { index }));
+        Some(spawned)
+    }
+
+    /// Forget the last move, its spawns and clears, to follow `GameState::undo`.
+    /// False if no moves are logged.
+    pub fn undo(&mut self) -> bool {
+        let Some(last) = self
+            .actions
+            .iter()
+            .rposition(|a| matches!(a, LogEntry::Move(_)))
+        else {
+            return false;
+        };
+        self.actions.truncate(last);
+        true
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn rules(&self) -> &Rules {
+        &self.rules
+    }
+
+    pub fn actions(&self) -> &[LogEntry] {
+        &self.actions
+    }
+
+    /// just the moves, in the order they were played
+    pub fn moves(&self) -> impl Iterator<Item = Direction> + '_ {
+        self.actions.iter().filter_map(|a| match a {
+            LogEntry::Move(d) => Some(*d),
+            LogEntry::Spawn { .. } | LogEntry::Clear { .. } => None,
+        })
+    }
+
+    pub fn move_count(&self) -> usize {
+        self.moves().count()
+    }
+
+    /// Replay the log onto an empty board under its rules. The result's RNG
+    /// is freshly seeded from the log's seed, so later spawns won't match the
+    /// original game's.
+    pub fn reconstruct(&self) -> GameState {
+        self.board_after(usize::MAX)
+    }
+
+    /// `reconstruct`, stopping after the first `moves` moves and what they
+    /// spawned and cleared
+    pub fn board_after(&self, moves: usize) -> GameState {
+        let mut gs =
+            GameState::from_list_seeded([-1; 16], self.seed).with_rules(self.rules.clone());
+        let mut played = 0;
+        for action in &self.actions {
+            match *action {
+                LogEntry::Spawn { index, tile } => gs.spawn_at(index, tile),
+                LogEntry::Clear { index } => gs.cells_mut()[index] = None,
+                LogEntry::Move(_) if played == moves => break,
+                LogEntry::Move(dir) => {
+                    gs.do_move(dir);
+                    played += 1;
+                }
+            }
+        }
+        gs
+    }
+}
+
+impl<'a> IntoIterator for &'a GameLog {
+    type Item = &'a LogEntry;
+    type IntoIter = std::slice::Iter<'a, LogEntry>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.actions.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{GameLog, LogEntry};
+    use crate::{Direction, GameState, Rules};
+
+    #[test]
+    fn serde_round_trip_and_reconstruct() {
+        let mut gs = GameState::new_from_seed(21);
+        let mut log = GameLog::new(&gs);
+        for d in [
+            Direction::Left,
+            Direction::Up,
+            Direction::Right,
+            Direction::Down,
+        ]
+        .repeat(4)
+        {
+            log.apply(&mut gs, d);
+        }
+
+        let json = serde_json::to_string(&log).unwrap();
+        let parsed: GameLog = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, log);
+
+        let rebuilt = parsed.reconstruct();
+        assert_eq!(rebuilt, gs);
+        assert_eq!(rebuilt.merge_score(), gs.merge_score());
+        assert_eq!(rebuilt.move_count(), gs.move_count());
+    }
+
+    #[test]
+    fn board_after_some_moves() {
+        let mut gs = GameState::new_from_seed(4);
+        let mut log = GameLog::new(&gs);
+        let mut boards = vec![gs.clone()];
+        for d in [Direction::Left, Direction::Down].repeat(5) {
+            if log.apply(&mut gs, d) {
+                boards.push(gs.clone());
+            }
+        }
+        assert_eq!(log.move_count(), boards.len() - 1);
+        for (k, board) in boards.iter().enumerate() {
+            assert_eq!(&log.board_after(k), board, "after {k} moves");
+        }
+        assert_eq!(log.board_after(1000), gs);
+
+        // undoing the log follows undoing the game
+        assert!(gs.undo());
+        assert!(log.undo());
+        assert_eq!(log.reconstruct(), gs);
+        assert_eq!(log.move_count(), boards.len() - 2);
+    }
+
+    #[test]
+    fn replays_keep_the_rules() {
+        let rules = Rules {
+            chain_merges: true,
+            move_limit: Some(3),
+            ..Rules::default()
+        };
+        let mut gs = GameState::from_list_seeded(
+            [2, 2, 4, 8, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+            6,
+        )
+        .with_rules(rules.clone());
+        let mut log = GameLog::new(&gs);
+        for d in Direction::ALL.repeat(2) {
+            log.apply(&mut gs, d);
+        }
+        assert!(gs.finished());
+
+        let parsed: GameLog = serde_json::from_str(&serde_json::to_string(&log).unwrap()).unwrap();
+        assert_eq!(parsed.rules(), &rules);
+        let rebuilt = parsed.reconstruct();
+        // a default-rules replay wouldn't chain the 2, 2, 4, 8 into a 16
+        assert_eq!(rebuilt, gs);
+        assert_eq!(rebuilt.merge_score(), gs.merge_score());
+        assert!(rebuilt.finished());
+    }
+
+    #[test]
+    fn zen_clears_are_replayed() {
+        #[rustfmt::skip]
+        let mut gs = GameState::from_list_seeded([
+            8,  16, 32,  64,
+            16, 32, 64,  128,
+            32, 64, 128, 256,
+            -1, 128, 256, 512,
+        ], 2)
+        .with_rules(Rules {
+            zen: true,
+            ..Rules::default()
+        });
+        let mut log = GameLog::new(&gs);
+        // fills the last cell, leaving the board stuck until zen clears it
+        assert!(log.apply(&mut gs, Direction::Left));
+        assert!(gs.empty_count() > 0);
+        assert!(log
+            .actions()
+            .iter()
+            .any(|a| matches!(a, LogEntry::Clear { .. })));
+        assert_eq!(log.reconstruct(), gs);
+        assert_eq!(log.board_after(1), gs);
+
+        assert!(gs.undo());
+        assert!(log.undo());
+        assert_eq!(log.reconstruct(), gs);
+    }
+
+    #[test]
+    fn iterate_entries_and_moves() {
+        let mut gs = GameState::from_list_seeded(
+            [2, 2, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+            3,
+        );
+        let mut log = GameLog::new(&gs);
+        assert!(log.apply(&mut gs, Direction::Left));
+        assert!(log.apply(&mut gs, Direction::Right));
+
+        let mut spawns = 0;
+        for entry in &log {
+            if let LogEntry::Spawn { .. } = entry {
+                spawns += 1;
+            }
+        }
+        assert_eq!(spawns, 4);
+        assert_eq!(
+            log.moves().collect::<Vec<_>>(),
+            [Direction::Left, Direction::Right]
+        );
+    }
+}
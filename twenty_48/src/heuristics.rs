@@ -0,0 +1,186 @@
+use crate::{Direction, GameState, Tile};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl Corner {
+    pub const ALL: [Corner; 4] = [
+        Corner::TopLeft,
+        Corner::TopRight,
+        Corner::BottomLeft,
+        Corner::BottomRight,
+    ];
+
+    /// (row, column) of the corner cell
+    fn position(self) -> (usize, usize) {
+        match self {
+            Corner::TopLeft => (0, 0),
+            Corner::TopRight => (0, 3),
+            Corner::BottomLeft => (3, 0),
+            Corner::BottomRight => (3, 3),
+        }
+    }
+
+    /// the two moves that push tiles into this corner
+    pub fn toward(self) -> [Direction; 2] {
+        match self {
+            Corner::TopLeft => [Direction::Up, Direction::Left],
+            Corner::TopRight => [Direction::Up, Direction::Right],
+            Corner::BottomLeft => [Direction::Down, Direction::Left],
+            Corner::BottomRight => [Direction::Down, Direction::Right],
+        }
+    }
+
+    /// corner closest to a cell, ties going to the top and left
+    pub fn nearest(row: usize, col: usize) -> Corner {
+        match (row < 2, col < 2) {
+            (true, true) => Corner::TopLeft,
+            (true, false) => Corner::TopRight,
+            (false, true) => Corner::BottomLeft,
+            (false, false) => Corner::BottomRight,
+        }
+    }
+
+    /// corner closest to the biggest tile, `None` on an empty board
+    pub fn of_max_tile(gs: &GameState) -> Option<Corner> {
+        let rows = gs.rows();
+        (0..4)
+            .flat_map(|r| (0..4).map(move |c| (r, c)))
+            .filter_map(|(r, c)| rows[r][c].map(|t| (t, r, c)))
+            .max_by_key(|(t, _, _)| *t)
+            .map(|(_, r, c)| Corner::nearest(r, c))
+    }
+}
+
+/// Sum of tile exponents weighted by closeness to `corner`: 6 in the corner
+/// itself down to 0 in the opposite one. Bigger when large tiles are packed
+/// toward the corner.
+pub fn corner_gradient(gs: &GameState, corner: Corner) -> i64 {
+    let (cr, cc) = corner.position();
+    let rows = gs.rows();
+    let mut total = 0;
+    for (r, row) in rows.iter().enumerate() {
+        for (c, t) in row.iter().enumerate() {
+            if let Some(t) = t {
+                let weight = 6 - (r.abs_diff(cr) + c.abs_diff(cc)) as i64;
+                total += i64::from(t.exponent()) * weight;
+            }
+        }
+    }
+    total
+}
+
+fn exponent(t: Option<Tile>) -> i64 {
+    t.map_or(0, |t| i64::from(t.exponent()))
+}
+
+/// How far each row and column is from being sorted, as a penalty: for
+/// every line, the smaller of its total exponent rises and total falls,
+/// summed and negated. 0 when every line only goes one way.
+pub fn monotonicity(gs: &GameState) -> i64 {
+    gs.lines()
+        .map(|line| {
+            let (mut rises, mut falls) = (0, 0);
+            for pair in line.windows(2) {
+                let step = exponent(pair[1]) - exponent(pair[0]);
+                if step > 0 {
+                    rises += step;
+                } else {
+                    falls -= step;
+                }
+            }
+            -i64::min(rises, falls)
+        })
+        .sum()
+}
+
+/// Exponent differences between tiles that are next to each other along a
+/// row or column once gaps are ignored, summed and negated. 0 when every
+/// line holds a single value, and lower the harder the board is to merge.
+pub fn smoothness(gs: &GameState) -> i64 {
+    gs.lines()
+        .map(|line| {
+            let tiles: Vec<i64> = line.iter().flatten().map(|t| exponent(Some(*t))).collect();
+            -tiles.windows(2).map(|w| (w[0] - w[1]).abs()).sum::<i64>()
+        })
+        .sum()
+}
+
+/// How much each heuristic counts toward `HeuristicWeights::score`. The
+/// defaults are what `evaluate` uses: the best `corner_gradient` plus 16 per
+/// empty cell, ignoring monotonicity and smoothness.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HeuristicWeights {
+    pub corner: f64,
+    pub empty: f64,
+    pub monotonicity: f64,
+    pub smoothness: f64,
+}
+
+impl Default for HeuristicWeights {
+    fn default() -> Self {
+        Self {
+            corner: 1.,
+            empty: 16.,
+            monotonicity: 0.,
+            smoothness: 0.,
+        }
+    }
+}
+
+impl HeuristicWeights {
+    /// weighted sum of the heuristics for `gs`, bigger is better
+    pub fn score(&self, gs: &GameState) -> f64 {
+        let gradient = Corner::ALL
+            .into_iter()
+            .map(|c| corner_gradient(gs, c))
+            .max()
+            .unwrap();
+        self.corner * gradient as f64
+            + self.empty * gs.empty_count() as f64
+            + self.monotonicity * monotonicity(gs) as f64
+            + self.smoothness * smoothness(gs) as f64
+    }
+}
+
+/// Static score of a position for search, `HeuristicWeights::default()`
+pub fn evaluate(gs: &GameState) -> f64 {
+    HeuristicWeights::default().score(gs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{corner_gradient, monotonicity, smoothness, Corner};
+    use crate::GameState;
+
+    #[test]
+    fn gradient_prefers_packed_corner() {
+        let gs = GameState::from_list([
+            64, 32, -1, -1, 16, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, 2,
+        ]);
+        assert_eq!(Corner::of_max_tile(&gs), Some(Corner::TopLeft));
+        let scores = Corner::ALL.map(|c| corner_gradient(&gs, c));
+        assert_eq!(scores.iter().max(), Some(&scores[0]));
+        // exponents 6, 5, 4 weighted 6, 5, 5, and the 2 in the far corner counts 0
+        assert_eq!(scores[0], 6 * 6 + 5 * 5 + 4 * 5);
+    }
+
+    #[test]
+    fn monotonicity_and_smoothness() {
+        let sorted = GameState::from_list([64, 32, 16, 8, 32, 16, 8, 4, 16, 8, 4, 2, 8, 4, 2, -1]);
+        assert_eq!(monotonicity(&sorted), 0);
+
+        // the top row falls 2, rises 2, then falls 3 into the empty cell
+        let zigzag =
+            GameState::from_list([8, 2, 8, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1]);
+        assert_eq!(monotonicity(&zigzag), -2);
+        // 8,2,8 differ by 2 twice, the gap is skipped
+        assert_eq!(smoothness(&zigzag), -4);
+        assert_eq!(smoothness(&GameState::from_list([4; 16])), 0);
+    }
+}
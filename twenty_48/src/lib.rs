@@ -1,22 +1,234 @@
+pub mod achievements;
+pub mod bench;
+pub mod game_log;
+pub mod heuristics;
+pub mod search;
 pub mod solvers;
+#[cfg(feature = "svg")]
+mod svg;
 
 use std::{
+    collections::VecDeque,
+    error::Error,
     fmt::{self, Display},
     num::NonZeroU32,
+    str::FromStr,
 };
 
-use rand::{rngs::StdRng, Rng, SeedableRng};
-use rand_distr::{Distribution, Standard, Uniform};
+use rand::{Rng, SeedableRng};
+use rand_distr::{Distribution, Uniform};
 
+/// A game on a board `W` tiles wide and `H` tall. Almost everything uses the
+/// classic 4x4 [`GameState`]; other sizes are for variants.
 #[derive(Clone, serde::Serialize, serde::Deserialize)]
-pub struct GameState {
-    nums: [Option<Tile>; 16],
+pub struct Board<const W: usize, const H: usize> {
+    /// rows top to bottom. Saved flattened in row-major order
+    #[serde(with = "flat_cells")]
+    nums: [[Option<Tile>; W]; H],
 
-    #[serde(skip_serializing, skip_deserializing, default = "StdRng::from_entropy")]
-    rng: StdRng,
+    /// Saved with its position, so a reloaded game spawns exactly what it
+    /// would have without the reload. Saves from before it was kept get a
+    /// fresh RNG.
+    #[serde(default = "GameRng::from_entropy")]
+    rng: GameRng,
+    /// what `rng` was last seeded with, if known, so the game can be restarted
+    #[serde(default)]
+    seed: Option<u64>,
+
+    #[serde(default)]
+    rules: Rules,
+
+    #[serde(default)]
+    move_count: u64,
+
+    #[serde(default)]
+    merge_score: u64,
+
+    /// undos taken this game, kept across the undos themselves
+    #[serde(default)]
+    undos_used: u64,
+
+    /// states before each `apply`, most recent last. Snapshots carry their
+    /// RNG, so undoing and replaying the same move reproduces the same spawn
+    #[serde(skip)]
+    history: VecDeque<Board<W, H>>,
+    /// most snapshots kept in `history`, the oldest are dropped past this
+    #[serde(skip, default = "default_undo_limit")]
+    undo_limit: usize,
+    /// states undone since the last `apply`, most recent last
+    #[serde(skip)]
+    future: Vec<Board<W, H>>,
+}
+
+/// The standard 4x4 game
+pub type GameState = Board<4, 4>;
+
+/// Where spawns come from. The same generator as rand's `StdRng` (so seeds
+/// give the same games they always have), but named so it can be saved.
+pub type GameRng = rand_chacha::ChaCha12Rng;
+
+/// Where a game ended up after `GameState::apply_all_then_report`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GameSummary {
+    pub moves: u64,
+    pub max_tile: u32,
+    pub merge_score: u64,
+    pub won: bool,
+}
+
+/// What one move would do, from `GameState::move_previews`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MovePreview {
+    pub merge_score: u64,
+    pub merges: usize,
+    pub empties_after: usize,
+}
+
+/// One merge made by `GameState::do_move_detailed`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Merge {
+    /// cell the merged tile ended up in, counted like spawn indices
+    pub index: usize,
+    pub tile: Tile,
+}
+
+impl Merge {
+    /// what the merge added to the merge score
+    pub fn points(&self) -> u64 {
+        u64::from(self.tile.as_u32())
+    }
+}
+
+/// What a move did to the board besides moving tiles
+pub(crate) struct MoveEffects {
+    pub spawned: Vec<(usize, Tile)>,
+    pub cleared: Vec<usize>,
+}
+
+/// Rule variants that change when a game is over
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Rules {
+    /// tile value that counts as winning
+    pub target: u32,
+    /// end the game as soon as `target` is reached instead of letting play continue
+    pub win_stops_game: bool,
+    /// which tiles spawn and how often
+    #[serde(default)]
+    pub spawn_table: SpawnTable,
+    /// end the game after this many moves
+    #[serde(default)]
+    pub move_limit: Option<u64>,
+    /// let a tile that just merged keep merging into the same cell while the
+    /// next tile matches it, so a row of 2, 2, 4, 8 collapses to a single 16
+    #[serde(default)]
+    pub chain_merges: bool,
+    /// whether undo is free, limited or costs points
+    #[serde(default)]
+    pub undo_policy: UndoPolicy,
+    /// tiles spawned after each move, fewer if the board runs out of room
+    #[serde(default = "one_spawn")]
+    pub spawns_per_move: usize,
+    /// never lose: a move that leaves the board stuck is followed by
+    /// `unstick`, clearing the smallest tiles
+    #[serde(default)]
+    pub zen: bool,
+}
+
+fn one_spawn() -> usize {
+    1
+}
+
+/// How much `GameState::undo` is allowed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum UndoPolicy {
+    #[default]
+    Free,
+    /// at most this many undos per game
+    Limited(u64),
+    /// every undo takes this many points off `effective_score`
+    Penalized(u64),
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            target: 2048,
+            win_stops_game: false,
+            spawn_table: SpawnTable::default(),
+            move_limit: None,
+            chain_merges: false,
+            undo_policy: UndoPolicy::Free,
+            spawns_per_move: 1,
+            zen: false,
+        }
+    }
+}
+
+/// Weighted choice of which tile spawns, 2@0.9 and 4@0.1 by default
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SpawnTable(Vec<(Tile, f64)>);
+
+#[derive(Debug, PartialEq)]
+pub enum InvalidSpawnTable {
+    Empty,
+    /// weights must be positive and finite
+    BadWeight(f64),
 }
 
-#[derive(Debug, Copy, Clone)]
+impl Display for InvalidSpawnTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidSpawnTable::Empty => write!(f, "spawn table has no entries"),
+            InvalidSpawnTable::BadWeight(w) => write!(f, "invalid spawn weight {w}"),
+        }
+    }
+}
+
+impl Error for InvalidSpawnTable {}
+
+impl SpawnTable {
+    pub fn new(entries: Vec<(Tile, f64)>) -> Result<Self, InvalidSpawnTable> {
+        if entries.is_empty() {
+            return Err(InvalidSpawnTable::Empty);
+        }
+        if let Some((_, w)) = entries.iter().find(|(_, w)| !(w.is_finite() && *w > 0.)) {
+            return Err(InvalidSpawnTable::BadWeight(*w));
+        }
+        Ok(Self(entries))
+    }
+
+    pub fn entries(&self) -> &[(Tile, f64)] {
+        &self.0
+    }
+
+    /// each tile with its weight normalized to a probability
+    pub fn probabilities(&self) -> impl Iterator<Item = (Tile, f64)> + '_ {
+        let total: f64 = self.0.iter().map(|(_, w)| w).sum();
+        self.0.iter().map(move |(t, w)| (*t, w / total))
+    }
+}
+
+impl Default for SpawnTable {
+    fn default() -> Self {
+        Self(vec![(Tile::TWO, 0.9), (Tile::FOUR, 0.1)])
+    }
+}
+
+impl Distribution<Tile> for SpawnTable {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Tile {
+        let total: f64 = self.0.iter().map(|(_, w)| w).sum();
+        let mut roll = rng.gen::<f64>() * total;
+        for (t, w) in &self.0 {
+            if roll < *w {
+                return *t;
+            }
+            roll -= w;
+        }
+        self.0.last().unwrap().0 // float rounding
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum Direction {
     Up,
     Down,
@@ -25,27 +237,117 @@ pub enum Direction {
 }
 
 impl Direction {
-    const ALL: [Direction; 4] = [
+    /// every direction, in the order tie-breaks elsewhere in the crate prefer
+    pub const ALL: [Direction; 4] = [
         Direction::Up,
         Direction::Down,
         Direction::Left,
         Direction::Right,
     ];
+
+    pub fn all() -> impl Iterator<Item = Direction> {
+        Direction::ALL.into_iter()
+    }
+
+    /// `u`, `d`, `l` or `r`, either case
+    pub fn from_char(c: char) -> Option<Direction> {
+        match c.to_ascii_lowercase() {
+            'u' => Some(Direction::Up),
+            'd' => Some(Direction::Down),
+            'l' => Some(Direction::Left),
+            'r' => Some(Direction::Right),
+            _ => None,
+        }
+    }
+
+    /// Moves written as words (`left up`) or letters (`lurd`), in any mix,
+    /// separated by whitespace or commas
+    pub fn parse_sequence(moves: &str) -> Result<Vec<Direction>, InvalidMove> {
+        let mut dirs = Vec::new();
+        for token in moves
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|t| !t.is_empty())
+        {
+            match token.to_ascii_lowercase().as_str() {
+                "up" => dirs.push(Direction::Up),
+                "down" => dirs.push(Direction::Down),
+                "left" => dirs.push(Direction::Left),
+                "right" => dirs.push(Direction::Right),
+                letters => {
+                    for c in letters.chars() {
+                        dirs.push(
+                            Direction::from_char(c).ok_or_else(|| InvalidMove(token.into()))?,
+                        );
+                    }
+                }
+            }
+        }
+        Ok(dirs)
+    }
 }
 
-impl GameState {
+/// something in a move string that isn't a direction
+#[derive(Debug, PartialEq, Eq)]
+pub struct InvalidMove(pub String);
+
+impl Display for InvalidMove {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "not a move: {:?}", self.0)
+    }
+}
+
+impl Error for InvalidMove {}
+
+impl<const W: usize, const H: usize> Board<W, H> {
     pub fn new_from_seed(seed: u64) -> Self {
-        Self::new(StdRng::seed_from_u64(seed))
+        Self::new(seed, Rules::default())
+    }
+
+    /// A game seeded from a shareable code, so everyone who types the same
+    /// code gets the same game. Case and surrounding whitespace don't matter.
+    pub fn new_from_code(code: &str) -> Self {
+        Self::new_from_seed(fnv1a(code.trim().to_lowercase().as_bytes()))
     }
 
+    /// a game with a random seed, which is still captured so it can be replayed
     pub fn new_from_entropy() -> Self {
-        Self::new(StdRng::from_entropy())
+        Self::new(GameRng::from_entropy().gen(), Rules::default())
+    }
+
+    /// a seeded game whose spawns, including the first two tiles, come from `spawn_table`
+    pub fn new_with_spawn_table(seed: u64, spawn_table: SpawnTable) -> Self {
+        Self::new(
+            seed,
+            Rules {
+                spawn_table,
+                ..Rules::default()
+            },
+        )
+    }
+
+    /// A two-tile starting game whose spawns all come from `rng`, for tests
+    /// that want a particular spawn sequence. The game has no seed, so it
+    /// can't be restarted.
+    pub fn with_rng(rng: GameRng) -> Self {
+        Self::new_with_rng(rng, None, Rules::default())
     }
 
-    fn new(rng: StdRng) -> Self {
-        let mut s = GameState {
-            nums: [None; 16],
+    fn new(seed: u64, rules: Rules) -> Self {
+        Self::new_with_rng(GameRng::seed_from_u64(seed), Some(seed), rules)
+    }
+
+    fn new_with_rng(rng: GameRng, seed: Option<u64>, rules: Rules) -> Self {
+        let mut s = Self {
+            nums: [[None; W]; H],
             rng,
+            seed,
+            rules,
+            move_count: 0,
+            merge_score: 0,
+            undos_used: 0,
+            history: VecDeque::new(),
+            undo_limit: DEFAULT_UNDO_LIMIT,
+            future: Vec::new(),
         };
 
         s.spawn_tile();
@@ -54,236 +356,2641 @@ impl GameState {
         s
     }
 
-    pub fn spawn_tile_with_dir(&mut self, dir: Direction) {
-        let rolls = [
-            self.random_open_tile().unwrap(),
-            self.random_open_tile().unwrap(),
-            self.random_open_tile().unwrap(),
-            self.random_open_tile().unwrap(),
-        ];
+    /// Spawn a random tile whether or not anything moved. `apply` is the
+    /// safe way to play; pairing this with `do_move` is the low-level path,
+    /// and spawning after a move that changed nothing hands out free tiles.
+    pub fn spawn_tile(&mut self) {
+        self.spawn_random();
+    }
+
+    /// spawn a tile from the spawn table in a random empty cell, returning where and what
+    pub(crate) fn spawn_random(&mut self) -> (usize, Tile) {
+        let t = self.random_open_tile().unwrap();
+        let tile = self.rules.spawn_table.sample(&mut self.rng);
+        self.cells_mut()[t] = Some(tile);
+        (t, tile)
+    }
 
-        let t = match dir {
-            Direction::Up => rolls[0],
-            Direction::Down => rolls[1],
-            Direction::Left => rolls[2],
-            Direction::Right => rolls[3],
+    /// The seed the RNG was last seeded with. `None` for boards built from a
+    /// list without one, or loaded from a save made before seeds were saved.
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    /// Start this game over: the same first two tiles and, given the same
+    /// moves, the same spawns after them. Rules and the undo limit are kept,
+    /// everything else goes. Returns false for games without a known seed.
+    pub fn restart(&mut self) -> bool {
+        let Some(seed) = self.seed else {
+            return false;
         };
+        let undo_limit = self.undo_limit;
+        *self = Self::new(seed, self.rules.clone());
+        self.undo_limit = undo_limit;
+        true
+    }
 
-        self.nums[t] = Some(self.rng.gen())
+    /// restart the spawn sequence from `seed`, leaving the board alone
+    pub fn reseed(&mut self, seed: u64) {
+        self.rng = GameRng::seed_from_u64(seed);
+        self.seed = Some(seed);
     }
 
-    pub fn spawn_tile(&mut self) {
-        let t = self.random_open_tile().unwrap();
-        self.nums[t] = Some(self.rng.gen())
+    pub fn with_rules(mut self, rules: Rules) -> Self {
+        self.rules = rules;
+        self
     }
 
-    pub fn lost(&self) -> bool {
-        Direction::ALL.iter().all(|d| !self.can_move(*d))
+    pub fn rules(&self) -> &Rules {
+        &self.rules
     }
 
-    pub fn rows(&self) -> [[Option<Tile>; 4]; 4] {
-        [
-            self.nums[0..4].try_into().unwrap(),
-            self.nums[4..8].try_into().unwrap(),
-            self.nums[8..12].try_into().unwrap(),
-            self.nums[12..16].try_into().unwrap(),
-        ]
+    /// `apply` each move in a string like `"lurd"` or `"left up"` (see
+    /// `Direction::parse_sequence`), returning how many were legal. Nothing
+    /// is played if any of the string isn't a move.
+    pub fn apply_str(&mut self, moves: &str) -> Result<usize, InvalidMove> {
+        let dirs = Direction::parse_sequence(moves)?;
+        Ok(dirs.into_iter().filter(|&d| self.apply(d)).count())
     }
 
-    fn _cols(&self) -> [[Option<Tile>; 4]; 4] {
-        [
-            [self.nums[0], self.nums[1], self.nums[2], self.nums[3]],
-            [self.nums[4], self.nums[5], self.nums[6], self.nums[7]],
-            [self.nums[8], self.nums[9], self.nums[10], self.nums[11]],
-            [self.nums[12], self.nums[13], self.nums[14], self.nums[15]],
-        ]
+    /// Play a move: check that it's legal, perform it and spawn a new tile.
+    /// Tiles spawn if and only if the board changed. Returns false and leaves
+    /// the game untouched, RNG included, if `dir` can't be played.
+    pub fn apply(&mut self, dir: Direction) -> bool {
+        self.apply_spawning(dir).is_some()
     }
 
-    pub fn can_move_col(&self, column: i32) -> bool {
-        self.can_move_colrow(column, Direction::Up) || self.can_move_colrow(column, Direction::Down)
+    /// `apply`, returning the cells and tiles that spawned after the move, in
+    /// spawn order, for callers that want to animate or log them. `None` if
+    /// `dir` can't be played.
+    pub fn apply_spawning(&mut self, dir: Direction) -> Option<Vec<(usize, Tile)>> {
+        self.apply_clearing(dir).map(|effects| effects.spawned)
     }
 
-    pub fn can_move_row(&self, row: i32) -> bool {
-        self.can_move_colrow(row, Direction::Left) || self.can_move_colrow(row, Direction::Right)
+    /// `apply_spawning`, also returning the cells zen mode's `unstick`
+    /// cleared after the spawns, so a `GameLog` can replay them
+    pub(crate) fn apply_clearing(&mut self, dir: Direction) -> Option<MoveEffects> {
+        if self.stopped_by_rules() || !self.can_move(dir) {
+            return None;
+        }
+        let before = self.snapshot();
+        self.do_move(dir);
+        // can_move and do_move share `slide_line` so they should never
+        // disagree, but if they did a spawn here would be a free tile for a
+        // move that didn't happen
+        if self.nums == before.nums {
+            debug_assert!(false, "can_move({dir:?}) but do_move changed nothing");
+            return None;
+        }
+        self.push_history(before);
+        self.future.clear();
+        let spawns = self.rules.spawns_per_move.min(self.empty_count());
+        let spawned = (0..spawns).map(|_| self.spawn_random()).collect();
+        let cleared = if self.rules.zen {
+            self.unstick()
+        } else {
+            Vec::new()
+        };
+        Some(MoveEffects { spawned, cleared })
     }
 
-    pub fn can_move_colrow(&self, colrow: i32, direction: Direction) -> bool {
-        let (dperp, dpar, start): (i32, i32, i32) = match direction {
-            Direction::Up => (4, 1, 0),
-            Direction::Down => (-4, 1, 12),
-            Direction::Left => (1, 4, 0),
-            Direction::Right => (-1, 4, 3),
+    /// Zen mode's way out of a lost position: remove every tile showing the
+    /// smallest value on the board. Returns the cells it cleared, in
+    /// row-major order, none if the board wasn't stuck.
+    pub fn unstick(&mut self) -> Vec<usize> {
+        if !self.lost() {
+            return Vec::new();
+        }
+        let Some(smallest) = self.cells().iter().flatten().min().copied() else {
+            return Vec::new();
         };
+        let mut removed = Vec::new();
+        for (index, cell) in self.cells_mut().iter_mut().enumerate() {
+            if *cell == Some(smallest) {
+                *cell = None;
+                removed.push(index);
+            }
+        }
+        removed
+    }
 
-        let s = start + colrow * dpar;
-        for perp_idx in 0..3 {
-            let idx = s + perp_idx * dperp;
-
-            for seekidx in 1..4 - perp_idx {
-                let n = (idx + seekidx * dperp) as usize;
-                if self.nums[n].is_some() {
-                    if self.nums[idx as usize].is_none() || self.nums[idx as usize] == self.nums[n]
-                    {
-                        return true;
-                    } else {
-                        break; // something in the way
-                    }
-                }
+    /// Keep at most `limit` moves of undo history, dropping the oldest
+    pub fn set_undo_limit(&mut self, limit: usize) {
+        self.undo_limit = limit;
+        while self.history.len() > limit {
+            self.history.pop_front();
+        }
+    }
+
+    fn push_history(&mut self, snapshot: Self) {
+        self.history.push_back(snapshot);
+        if self.history.len() > self.undo_limit {
+            self.history.pop_front();
+        }
+    }
+
+    /// The board after moving `dir`, without spawning a tile or touching
+    /// this game. `None` if the move isn't legal. The preview has no undo history.
+    pub fn preview_move(&self, dir: Direction) -> Option<Self> {
+        if !self.can_move(dir) {
+            return None;
+        }
+        let mut preview = self.snapshot();
+        preview.do_move(dir);
+        Some(preview)
+    }
+
+    /// `apply` on a copy: the game after moving `dir` and spawning, or `None`
+    /// if the move isn't legal. The copy's RNG has advanced past the spawn,
+    /// while this game's hasn't, so applying `dir` here gives the same board.
+    /// Like `preview_move` it skips the undo history, except for this move.
+    pub fn simulate(&self, dir: Direction) -> Option<Self> {
+        let mut next = self.snapshot();
+        next.apply(dir).then_some(next)
+    }
+
+    /// Play moves picked by `choose` until it returns `None`, picks an
+    /// illegal move, or the game is finished, then report how the game
+    /// stands. `moves` counts the whole game, not just this call.
+    pub fn apply_all_then_report(
+        &mut self,
+        mut choose: impl FnMut(&Self) -> Option<Direction>,
+    ) -> GameSummary {
+        while !self.finished() {
+            match choose(self) {
+                Some(d) if self.apply(d) => {}
+                _ => break,
             }
         }
-        false
+        GameSummary {
+            moves: self.move_count,
+            max_tile: self.max(),
+            merge_score: self.merge_score,
+            won: self.won(),
+        }
     }
 
-    pub fn can_move(&self, direction: Direction) -> bool {
-        (0..4).any(|colrow| self.can_move_colrow(colrow, direction))
+    /// merge score a move in `dir` would gain, `None` if it isn't legal
+    pub fn score_if_moved(&self, dir: Direction) -> Option<u64> {
+        self.preview_move(dir)
+            .map(|p| p.merge_score - self.merge_score)
     }
 
-    pub fn do_move(&mut self, direction: Direction) {
-        let (dperp, dpar, start): (i32, i32, i32) = match direction {
-            Direction::Up => (4, 1, 0),
-            Direction::Down => (-4, 1, 12),
-            Direction::Left => (1, 4, 0),
-            Direction::Right => (-1, 4, 3),
-        };
+    /// how many merges a move in `dir` would perform; each one removes a tile
+    pub fn merge_count(&self, dir: Direction) -> usize {
+        match self.preview_move(dir) {
+            Some(p) => self.cells().iter().flatten().count() - p.cells().iter().flatten().count(),
+            None => 0,
+        }
+    }
 
-        for par_idx in 0..4 {
-            let s = start + par_idx * dpar;
-            for perp_idx in 0..3 {
-                let idx = s + perp_idx * dperp;
-
-                for seekidx in 1..4 - perp_idx {
-                    let n = (idx + seekidx * dperp) as usize;
-                    if self.nums[n].is_some() {
-                        if self.nums[idx as usize] == self.nums[n] {
-                            self.nums[idx as usize] =
-                                Some(self.nums[idx as usize].as_mut().unwrap().double());
-                            self.nums[n] = None;
-                            break;
-                        } else if self.nums[idx as usize].is_none() {
-                            self.nums[idx as usize] = self.nums[n];
-                            self.nums[n] = None;
-                        } else {
-                            break; // something in the way
-                        }
-                    }
-                }
+    /// What each move would do, in `Direction::ALL` order, `None` for
+    /// illegal ones. Empties are counted before the spawn.
+    pub fn move_previews(&self) -> [Option<MovePreview>; 4] {
+        Direction::ALL.map(|d| {
+            self.preview_move(d).map(|p| MovePreview {
+                merge_score: p.merge_score - self.merge_score,
+                merges: p.empty_count() - self.empty_count(),
+                empties_after: p.empty_count(),
+            })
+        })
+    }
+
+    /// The legal move with the biggest immediate merge score, and that
+    /// score. Earlier directions in `Direction::ALL` win ties.
+    pub fn best_merge_cell(&self) -> Option<(Direction, u64)> {
+        Direction::ALL
+            .into_iter()
+            .filter_map(|d| self.score_if_moved(d).map(|s| (d, s)))
+            .fold(None, |best, (d, s)| match best {
+                Some((_, b)) if b >= s => best,
+                _ => Some((d, s)),
+            })
+    }
+
+    /// The legal move whose merges make the biggest single tile, and that
+    /// tile's value. `None` if no move merges anything. Earlier directions in
+    /// `Direction::ALL` win ties.
+    pub fn largest_merge_result(&self) -> Option<(Direction, u32)> {
+        Direction::ALL
+            .into_iter()
+            .filter(|&d| self.can_move(d))
+            .filter_map(|d| {
+                let mut preview = self.snapshot();
+                let biggest = preview.do_move_detailed(d).iter().map(|m| m.tile).max()?;
+                Some((d, biggest.as_u32()))
+            })
+            .fold(None, |best, (d, t)| match best {
+                Some((_, b)) if b >= t => best,
+                _ => Some((d, t)),
+            })
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.history.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.future.is_empty()
+    }
+
+    /// Go back to the state before the last `apply`, RNG included, so
+    /// replaying the same move spawns the same tile. Returns false if
+    /// there's nothing to undo.
+    pub fn undo(&mut self) -> bool {
+        if self.undos_remaining() == Some(0) {
+            return false;
+        }
+        match self.history.pop_back() {
+            Some(prev) => {
+                let current = std::mem::replace(self, prev);
+                self.undos_used = current.undos_used + 1;
+                self.history = current.history;
+                self.undo_limit = current.undo_limit;
+                self.future = current.future;
+                self.future.push(Self {
+                    history: VecDeque::new(),
+                    future: Vec::new(),
+                    ..current
+                });
+                true
             }
+            None => false,
         }
     }
 
-    fn random_open_tile(&mut self) -> Option<usize> {
-        let open_tiles = self.nums.iter().filter(|t| t.is_none()).count();
-        if open_tiles == 0 {
-            None
-        } else {
-            Some(
-                self.nums
-                    .iter()
-                    .enumerate()
-                    .filter(|(_, t)| t.is_none())
-                    .nth(self.rng.sample(Uniform::new(0, open_tiles)))
-                    .unwrap()
-                    .0,
-            )
+    /// undos taken this game, whatever the undo policy
+    pub fn undos_used(&self) -> u64 {
+        self.undos_used
+    }
+
+    /// undos left under `UndoPolicy::Limited`, `None` when they aren't limited
+    pub fn undos_remaining(&self) -> Option<u64> {
+        match self.rules.undo_policy {
+            UndoPolicy::Limited(limit) => Some(limit.saturating_sub(self.undos_used)),
+            _ => None,
         }
     }
 
-    pub fn from_list(arg: [i32; 16]) -> Self {
-        let mut nums = [None; 16];
-        for (i, n) in arg.iter().enumerate() {
-            if *n != -1 {
-                nums[i] = Some(Tile(NonZeroU32::new(n.checked_ilog2().unwrap()).unwrap()));
+    /// points lost to undos so far under `UndoPolicy::Penalized`
+    pub fn undo_penalty(&self) -> u64 {
+        match self.rules.undo_policy {
+            UndoPolicy::Penalized(cost) => cost.saturating_mul(self.undos_used),
+            _ => 0,
+        }
+    }
+
+    /// `score` less the undo penalty, what a finished game should be recorded as
+    pub fn effective_score(&self) -> u64 {
+        self.score().saturating_sub(self.undo_penalty())
+    }
+
+    /// Reapply the last undone move. Returns false if there's nothing to redo.
+    pub fn redo(&mut self) -> bool {
+        match self.future.pop() {
+            Some(next) => {
+                let current = std::mem::replace(self, next);
+                self.undos_used = current.undos_used;
+                self.history = current.history;
+                self.undo_limit = current.undo_limit;
+                self.future = current.future;
+                self.push_history(Self {
+                    history: VecDeque::new(),
+                    future: Vec::new(),
+                    ..current
+                });
+                true
             }
+            None => false,
         }
+    }
 
+    /// copy of the game without its undo/redo stacks
+    fn snapshot(&self) -> Self {
         Self {
-            nums,
-            rng: StdRng::from_entropy(),
+            nums: self.nums,
+            rng: self.rng.clone(),
+            seed: self.seed,
+            rules: self.rules.clone(),
+            move_count: self.move_count,
+            merge_score: self.merge_score,
+            undos_used: self.undos_used,
+            history: VecDeque::new(),
+            undo_limit: self.undo_limit,
+            future: Vec::new(),
         }
     }
 
-    pub fn max(&self) -> u32 {
-        self.nums
+    /// Every spawn that could follow, as `(index, tile, probability)`. Empty
+    /// if the board is full.
+    pub fn possible_spawns(&self) -> impl Iterator<Item = (usize, Tile, f64)> + '_ {
+        let empties = self.empty_count() as f64;
+        self.cells()
             .iter()
-            .filter_map(|t| t.map(|t| t.as_u32()))
-            .max()
-            .unwrap()
+            .enumerate()
+            .filter(|(_, t)| t.is_none())
+            .flat_map(move |(i, _)| {
+                self.rules
+                    .spawn_table
+                    .probabilities()
+                    .map(move |(t, p)| (i, t, p / empties))
+            })
     }
 
-    pub fn print(&self) {
-        println!("{self}");
+    /// place `tile` at `index`, which must be empty
+    pub fn spawn_at(&mut self, index: usize, tile: Tile) {
+        assert!(self.cells()[index].is_none(), "cell {index} is occupied");
+        self.cells_mut()[index] = Some(tile);
     }
-    fn print_row(f: &mut impl fmt::Write, row: &[Option<Tile>]) -> fmt::Result {
-        for tile in row.iter() {
-            match tile {
-                Some(tile) => write!(f, "|{: ^5}", tile.as_u32())?,
-                None => write!(f, "|{: ^5}", " ")?,
-            }
+
+    /// Fill the lowest-index empty cell with a 2, without touching the RNG.
+    ///
+    /// This is for building exact move sequences in tests, not for gameplay.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn spawn_first_open(&mut self) -> Option<usize> {
+        let t = self.cells().iter().position(|t| t.is_none())?;
+        self.cells_mut()[t] = Some(Tile::TWO);
+        Some(t)
+    }
+
+    pub fn lost(&self) -> bool {
+        Direction::ALL.iter().all(|d| !self.can_move(*d))
+    }
+
+    /// directions that would change the board, in `Direction::ALL` order
+    pub fn valid_moves(&self) -> Vec<Direction> {
+        Direction::ALL
+            .into_iter()
+            .filter(|d| self.can_move(*d))
+            .collect()
+    }
+
+    /// A uniformly random legal move drawn from the game's RNG, `None` if
+    /// there isn't one. The draw comes from a separate stream of the RNG at
+    /// its current position, so it doesn't change what spawns next; asking
+    /// again before a move is played gives the same answer.
+    pub fn pick_random_move(&self) -> Option<Direction> {
+        use rand::seq::SliceRandom;
+
+        let mut rng = self.rng.clone();
+        rng.set_stream(1);
+        self.valid_moves().choose(&mut rng).copied()
+    }
+
+    /// the one direction left, when every other move would do nothing
+    pub fn only_move(&self) -> Option<Direction> {
+        match self.valid_moves()[..] {
+            [d] => Some(d),
+            _ => None,
         }
-        Ok(())
     }
 
-    pub fn score(&self) -> u64 {
-        self.nums
+    /// Early warning that the game is about to be lost: at most one empty
+    /// cell, and at most two merges across all four directions (so a single
+    /// pair, which both of its directions count). False once actually lost.
+    pub fn is_near_loss(&self) -> bool {
+        let merges: usize = Direction::all().map(|d| self.merge_count(d)).sum();
+        self.empty_count() <= 1 && merges <= 2 && !self.lost()
+    }
+
+    /// Structural sanity check for boards that came from outside, like
+    /// deserialized JSON: every tile's exponent is one `Tile::from_exponent`
+    /// accepts, the spawn table could have been built by `SpawnTable::new`,
+    /// and the move and undo counts are within their limits.
+    pub fn is_valid(&self) -> bool {
+        let valid_tile = |t: &Tile| Tile::from_exponent(t.exponent()).is_some();
+        let table = self.rules.spawn_table.entries();
+
+        self.cells().iter().flatten().all(valid_tile)
+            && table.iter().all(|(t, _)| valid_tile(t))
+            && SpawnTable::new(table.to_vec()).is_ok()
+            && self.rules.move_limit.is_none_or(|l| self.move_count <= l)
+            && match self.rules.undo_policy {
+                UndoPolicy::Limited(l) => self.undos_used <= l,
+                _ => true,
+            }
+    }
+
+    /// how many tiles show exactly `value`, 0 for values no tile can have
+    pub fn count_tile(&self, value: u32) -> usize {
+        self.cells()
             .iter()
             .flatten()
-            .map(|t| u64::from(t.as_u32()))
-            .sum()
+            .filter(|t| t.as_u32() == value)
+            .count()
     }
-}
 
-impl Display for GameState {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for row in self.rows().iter() {
-            GameState::print_row(f, row)?;
-            writeln!(f, "|")?;
+    /// true if any tile is at least `target`
+    pub fn has_reached(&self, target: u32) -> bool {
+        self.cells().iter().flatten().any(|t| t.as_u32() >= target)
+    }
+
+    pub fn won(&self) -> bool {
+        self.has_reached(self.rules.target)
+    }
+
+    /// Cheap check for lost causes: false when no tile of at least `target`
+    /// can ever appear. Moves never change the total of the tiles, only
+    /// spawns add to it, so the total plus all the remaining moves could
+    /// spawn has to reach `target`. Without a move limit that only fails
+    /// once the game is over. True doesn't promise the target is reachable.
+    pub fn target_reachable(&self, target: u32) -> bool {
+        if self.has_reached(target) {
+            return true;
         }
-        Ok(())
+        if self.finished() {
+            return false;
+        }
+        let Some(moves) = self.moves_remaining() else {
+            return true;
+        };
+        let total = self.total_value();
+        let biggest_spawn = self
+            .rules
+            .spawn_table
+            .entries()
+            .iter()
+            .map(|(t, _)| u64::from(t.as_u32()))
+            .max()
+            .unwrap_or(0);
+        let spawnable = moves
+            .saturating_mul(self.rules.spawns_per_move as u64)
+            .saturating_mul(biggest_spawn);
+        total.saturating_add(spawnable) >= u64::from(target)
     }
-}
 
-// which power of two. NonZero because two is the lowest
-#[derive(Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
-pub struct Tile(NonZeroU32);
+    /// no more moves will be accepted, either because the game is lost or
+    /// because the rules ended it
+    pub fn finished(&self) -> bool {
+        self.stopped_by_rules() || self.lost()
+    }
 
-impl Tile {
-    const TWO: Tile = Tile(NonZeroU32::new(1).unwrap());
-    const FOUR: Tile = Tile::TWO.double();
+    /// moves left under `move_limit`, if there is one
+    pub fn moves_remaining(&self) -> Option<u64> {
+        self.rules
+            .move_limit
+            .map(|l| l.saturating_sub(self.move_count))
+    }
 
-    const fn double(&self) -> Tile {
-        Tile(NonZeroU32::new(self.0.get() + 1).unwrap())
+    /// won under `win_stops_game` or out of moves under `move_limit`
+    fn stopped_by_rules(&self) -> bool {
+        (self.rules.win_stops_game && self.won()) || self.moves_remaining() == Some(0)
     }
 
-    pub fn as_u32(&self) -> u32 {
-        2_u32.pow(self.0.get())
+    pub fn rows(&self) -> [[Option<Tile>; W]; H] {
+        self.nums
     }
 
-    pub fn exponent(&self) -> u32 {
-        self.0.get()
+    /// `rows`, but with tiles as the numbers shown on them
+    pub fn values(&self) -> [[Option<TileValue>; W]; H] {
+        self.nums.map(|row| row.map(|t| t.map(TileValue::from)))
     }
-}
 
-impl Distribution<Tile> for Standard {
-    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Tile {
-        if rng.sample(rand::distributions::Bernoulli::new(0.9).unwrap()) {
-            Tile::TWO
-        } else {
-            Tile::FOUR
+    /// columns left to right, each read top to bottom
+    pub fn cols(&self) -> [[Option<Tile>; H]; W] {
+        std::array::from_fn(|c| std::array::from_fn(|r| self.nums[r][c]))
+    }
+
+    /// Indices of the cells directly above, left of, right of and below the
+    /// cell at `index`, in that (increasing) order: 2 in a corner, 3 along an
+    /// edge, 4 anywhere else. Panics if `index` is off the board.
+    pub fn neighbors(index: usize) -> impl Iterator<Item = usize> {
+        assert!(index < W * H, "cell {index} is off the board");
+        let (r, c) = (index / W, index % W);
+        [
+            (r > 0).then(|| index - W),
+            (c > 0).then(|| index - 1),
+            (c + 1 < W).then(|| index + 1),
+            (r + 1 < H).then(|| index + W),
+        ]
+        .into_iter()
+        .flatten()
+    }
+
+    /// cells in row-major order, the order spawn indices count in
+    fn cells(&self) -> &[Option<Tile>] {
+        self.nums.as_flattened()
+    }
+
+    fn cells_mut(&mut self) -> &mut [Option<Tile>] {
+        self.nums.as_flattened_mut()
+    }
+
+    /// How `direction` walks the board in row-major indices: the step along
+    /// a line away from the edge tiles slide toward, the step from one line
+    /// to the next, the edge cell of the first line, the number of lines and
+    /// their length.
+    fn walk(direction: Direction) -> (i32, i32, i32, i32, i32) {
+        let (w, h) = (W as i32, H as i32);
+        match direction {
+            Direction::Up => (w, 1, 0, w, h),
+            Direction::Down => (-w, 1, (h - 1) * w, w, h),
+            Direction::Left => (1, w, 0, h, w),
+            Direction::Right => (-1, w, w - 1, h, w),
         }
     }
-}
 
-#[cfg(test)]
-mod test {
-    use crate::{Direction, GameState};
+    pub fn can_move_col(&self, column: i32) -> bool {
+        self.can_move_colrow(column, Direction::Up) || self.can_move_colrow(column, Direction::Down)
+    }
 
-    // | 128 | 64  | 32  |  8  |
-    // |  8  |  4  |  8  |  4  |
-    // |     |     |     |     |
-    // |     |     |     |     |
-    #[test]
-    fn testcase1() {
-        let gs = GameState::from_list([128, 64, 32, 8, 8, 4, 8, 4, -1, -1, -1, -1, -1, -1, -1, -1]);
-        assert!(!gs.can_move(Direction::Right));
+    pub fn can_move_row(&self, row: i32) -> bool {
+        self.can_move_colrow(row, Direction::Left) || self.can_move_colrow(row, Direction::Right)
+    }
+
+    pub fn can_move_colrow(&self, colrow: i32, direction: Direction) -> bool {
+        let mut scratch = self.nums;
+        Self::slide_line(
+            scratch.as_flattened_mut(),
+            direction,
+            colrow,
+            self.rules.chain_merges,
+            |_, _| {},
+        )
+    }
+
+    pub fn can_move(&self, direction: Direction) -> bool {
+        let (_, _, _, lines, _) = Self::walk(direction);
+        (0..lines).any(|colrow| self.can_move_colrow(colrow, direction))
+    }
+
+    /// Slide and merge line `colrow` of `nums` toward the edge `direction`
+    /// points at, calling `on_merge` with each merged tile and the index in
+    /// `nums` it landed at. This is the one
+    /// place moves happen, so `can_move` (which slides a scratch copy) and
+    /// `do_move` can't disagree. Returns whether anything moved.
+    fn slide_line(
+        nums: &mut [Option<Tile>],
+        direction: Direction,
+        colrow: i32,
+        chain_merges: bool,
+        mut on_merge: impl FnMut(usize, Tile),
+    ) -> bool {
+        let (dperp, dpar, start, _, len) = Self::walk(direction);
+        let s = start + colrow * dpar;
+        let cell = |i: usize| (s + i as i32 * dperp) as usize;
+
+        // a board's worth of cells always has room for one line
+        let mut line = [[None; W]; H];
+        let line = &mut line.as_flattened_mut()[..len as usize];
+        for (i, t) in line.iter_mut().enumerate() {
+            *t = nums[cell(i)];
+        }
+        let changed = merge_cells(line, chain_merges, |i, t| on_merge(cell(i), t));
+        if changed {
+            for (i, t) in line.iter().enumerate() {
+                nums[cell(i)] = *t;
+            }
+        }
+        changed
+    }
+
+    /// Slide and merge the tiles without spawning, checking legality, or
+    /// recording undo history. The low-level half of `apply`, for replays and
+    /// analysis that place spawns themselves.
+    pub fn do_move(&mut self, direction: Direction) {
+        self.move_tiles(direction, |_, _| {});
+    }
+
+    /// `do_move`, also returning every merge it made, in the order they
+    /// happened. Their points add up to what the move scored.
+    pub fn do_move_detailed(&mut self, direction: Direction) -> Vec<Merge> {
+        let mut merges = Vec::new();
+        self.move_tiles(direction, |index, tile| merges.push(Merge { index, tile }));
+        merges
+    }
+
+    fn move_tiles(&mut self, direction: Direction, mut on_merge: impl FnMut(usize, Tile)) {
+        if self.stopped_by_rules() {
+            return;
+        }
+        let before = self.nums;
+        #[cfg(debug_assertions)]
+        let merge_score_before = self.merge_score;
+
+        let (_, _, _, lines, _) = Self::walk(direction);
+        for colrow in 0..lines {
+            Self::slide_line(
+                self.nums.as_flattened_mut(),
+                direction,
+                colrow,
+                self.rules.chain_merges,
+                |index, merged| {
+                    self.merge_score = self.merge_score.saturating_add(u64::from(merged.as_u32()));
+                    on_merge(index, merged);
+                },
+            );
+        }
+
+        if self.nums != before {
+            self.move_count += 1;
+        }
+
+        #[cfg(debug_assertions)]
+        self.check_move_invariants(&before, merge_score_before);
+    }
+
+    /// Sanity checks on the result of `do_move`, compiled out of release builds.
+    ///
+    /// A move only slides and merges tiles, so the sum of tile values never
+    /// changes. Each merge removes one tile and adds the merged tile's value
+    /// (at least 4) to `merge_score`, so the score grows by at least 4 per
+    /// lost tile and always by an even amount, until it saturates.
+    #[cfg(debug_assertions)]
+    fn check_move_invariants(&self, before: &[[Option<Tile>; W]; H], merge_score_before: u64) {
+        let value = |nums: &[[Option<Tile>; W]; H]| -> u64 {
+            nums.iter()
+                .flatten()
+                .flatten()
+                .map(|t| u64::from(t.as_u32()))
+                .sum()
+        };
+        let count = |nums: &[[Option<Tile>; W]; H]| nums.iter().flatten().flatten().count() as u64;
+
+        debug_assert_eq!(
+            value(before),
+            value(&self.nums),
+            "move changed total tile value"
+        );
+
+        let merges = count(before) - count(&self.nums);
+        let gained = self.merge_score - merge_score_before;
+        // a saturated score stops growing, so only the no-merge case still holds
+        let saturated = self.merge_score == u64::MAX;
+        debug_assert!(
+            (saturated || (gained.is_multiple_of(2) && gained >= 4 * merges))
+                && (merges > 0 || gained == 0),
+            "{merges} merges scored {gained}"
+        );
+    }
+
+    /// number of moves that changed the board
+    pub fn move_count(&self) -> u64 {
+        self.move_count
+    }
+
+    /// Classic 2048 score: the value of every tile created by a merge.
+    /// Saturates at `u64::MAX` instead of wrapping.
+    pub fn merge_score(&self) -> u64 {
+        self.merge_score
+    }
+
+    /// A uniformly random empty cell. Solvers spawn millions of tiles, so the
+    /// empties are gathered in one pass into a board-sized array on the stack.
+    /// Draws from the RNG the same way as always, so seeded games don't change.
+    fn random_open_tile(&mut self) -> Option<usize> {
+        let mut empties = [[0; W]; H];
+        let empties = empties.as_flattened_mut();
+        let mut open_tiles = 0;
+        for (i, t) in self.cells().iter().enumerate() {
+            if t.is_none() {
+                empties[open_tiles] = i;
+                open_tiles += 1;
+            }
+        }
+        if open_tiles == 0 {
+            None
+        } else {
+            Some(empties[self.rng.sample(Uniform::new(0, open_tiles))])
+        }
+    }
+
+    /// Build a board from tile values row by row, -1 for an empty cell
+    pub fn from_rows(rows: [[i32; W]; H]) -> Self {
+        Self::from_rows_with_rng(rows, GameRng::from_entropy(), None)
+    }
+
+    /// like `from_rows`, but future spawns are determined by `seed`
+    pub fn from_rows_seeded(rows: [[i32; W]; H], seed: u64) -> Self {
+        Self::from_rows_with_rng(rows, GameRng::seed_from_u64(seed), Some(seed))
+    }
+
+    /// Build a board from tile exponents row by row, 0 for an empty cell and
+    /// 11 for 2048. `None` if any exponent is past 31, which no tile can have.
+    pub fn from_exponents(grid: [[u32; W]; H]) -> Option<Self> {
+        let mut nums = [[None; W]; H];
+        for (cells, row) in nums.iter_mut().zip(grid) {
+            for (cell, e) in cells.iter_mut().zip(row) {
+                if e != 0 {
+                    *cell = Some(Tile::from_exponent(e)?);
+                }
+            }
+        }
+        Some(Self::from_cells(nums, GameRng::from_entropy(), None))
+    }
+
+    fn from_rows_with_rng(rows: [[i32; W]; H], rng: GameRng, seed: Option<u64>) -> Self {
+        let nums = rows.map(|row| {
+            row.map(|n| {
+                (n != -1).then(|| Tile(NonZeroU32::new(n.checked_ilog2().unwrap()).unwrap()))
+            })
+        });
+        Self::from_cells(nums, rng, seed)
+    }
+
+    fn from_cells(nums: [[Option<Tile>; W]; H], rng: GameRng, seed: Option<u64>) -> Self {
+        Self {
+            nums,
+            rng,
+            seed,
+            rules: Rules::default(),
+            move_count: 0,
+            merge_score: 0,
+            undos_used: 0,
+            history: VecDeque::new(),
+            undo_limit: DEFAULT_UNDO_LIMIT,
+            future: Vec::new(),
+        }
+    }
+
+    pub fn max(&self) -> u32 {
+        self.cells()
+            .iter()
+            .filter_map(|t| t.map(|t| t.as_u32()))
+            .max()
+            .unwrap()
+    }
+
+    /// Sum of every tile on the board. Merges keep it the same, so it only
+    /// grows by what spawns; anything else means the move code made or lost
+    /// a tile.
+    pub fn total_value(&self) -> u64 {
+        self.cells()
+            .iter()
+            .flatten()
+            .map(|t| u64::from(t.as_u32()))
+            .sum()
+    }
+
+    /// the `n` biggest tiles, biggest first, or every tile if there are fewer
+    pub fn largest_n_tiles(&self, n: usize) -> Vec<Tile> {
+        let mut tiles: Vec<Tile> = self.cells().iter().flatten().copied().collect();
+        tiles.sort_unstable_by(|a, b| b.cmp(a));
+        tiles.truncate(n);
+        tiles
+    }
+
+    /// Single line form of the board for logs, e.g. `2,4,.,8/16,.,.,2/.,.,.,./.,.,.,.`
+    pub fn to_compact(&self) -> String {
+        self.rows()
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|t| t.map_or(".".to_string(), |t| t.as_u32().to_string()))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    /// The board as exponents (1 for a 2, 11 for 2048), two characters a
+    /// cell, one row per line. Handy when eyeballing solver and heuristic
+    /// code, which thinks in exponents too.
+    pub fn debug_exponents(&self) -> String {
+        self.rows()
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|t| t.map_or(" .".to_string(), |t| format!("{:>2}", t.exponent())))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub fn empty_count(&self) -> usize {
+        self.cells().iter().filter(|t| t.is_none()).count()
+    }
+
+    /// share of cells holding a tile, from 0 (empty) to 1 (full)
+    pub fn fill_fraction(&self) -> f32 {
+        let cells = (W * H) as f32;
+        (cells - self.empty_count() as f32) / cells
+    }
+
+    /// How close to stuck the position is, from 0 (empty) to 1 (lost):
+    ///
+    /// `fill * (1 + blocked + unmergeable) / 3`
+    ///
+    /// where `fill` is `fill_fraction`, `blocked` the share of the four
+    /// directions that can't be played, and `unmergeable` is
+    /// `1 / (1 + merge_potential)`. Scaling by `fill` keeps sparse boards
+    /// easy even though they have few merges lined up.
+    pub fn difficulty_score(&self) -> f64 {
+        let fill = f64::from(self.fill_fraction());
+        let blocked = 1. - self.valid_moves().len() as f64 / 4.;
+        let unmergeable = 1. / (1. + self.merge_potential() as f64);
+        fill * (1. + blocked + unmergeable) / 3.
+    }
+
+    /// Pairs of equal tiles side by side or one above the other, with
+    /// nothing between them. Cheaper than previewing every move, and more
+    /// pairs leaves more ways out.
+    pub fn merge_potential(&self) -> usize {
+        let cells = self.cells();
+        (0..cells.len())
+            .filter(|&i| cells[i].is_some())
+            .map(|i| {
+                Self::neighbors(i)
+                    .filter(|&n| n > i && cells[n] == cells[i])
+                    .count()
+            })
+            .sum()
+    }
+
+    /// Stable identifier for this position, built from the board, move count
+    /// and score. The same finished game always gives the same value.
+    pub fn fingerprint(&self) -> u64 {
+        let mut bytes: Vec<u8> = self
+            .cells()
+            .iter()
+            .map(|t| t.map_or(0, |t| t.exponent() as u8))
+            .collect();
+        bytes.extend(self.move_count.to_le_bytes());
+        bytes.extend(self.merge_score.to_le_bytes());
+        fnv1a(&bytes)
+    }
+
+    /// true if both boards hold the same tile values, wherever they are
+    pub fn same_tiles(&self, other: &Self) -> bool {
+        let sorted = |gs: &Self| {
+            let mut tiles: Vec<Tile> = gs.cells().iter().flatten().copied().collect();
+            tiles.sort();
+            tiles
+        };
+        sorted(self) == sorted(other)
+    }
+
+    pub fn print(&self) {
+        println!("{self}");
+    }
+    fn print_row(f: &mut impl fmt::Write, row: &[Option<Tile>]) -> fmt::Result {
+        for tile in row.iter() {
+            match tile {
+                Some(tile) => write!(f, "|{: ^5}", tile.as_u32())?,
+                None => write!(f, "|{: ^5}", " ")?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Sum of the tiles on the board. Saturates at `u64::MAX`, though that
+    /// takes a board of 2^33 cells even with every tile at the 2^31 maximum.
+    pub fn score(&self) -> u64 {
+        self.cells()
+            .iter()
+            .flatten()
+            .fold(0u64, |sum, t| sum.saturating_add(u64::from(t.as_u32())))
+    }
+}
+
+impl GameState {
+    pub fn from_list(arg: [i32; 16]) -> Self {
+        Self::from_rows(list_to_rows(arg))
+    }
+
+    /// like `from_list`, but future spawns are determined by `seed`
+    pub fn from_list_seeded(arg: [i32; 16], seed: u64) -> Self {
+        Self::from_rows_seeded(list_to_rows(arg), seed)
+    }
+
+    /// Parse the output of `to_compact`. Unlike `FromStr`, this insists on
+    /// four slash-separated rows of four cells.
+    pub fn from_compact(s: &str) -> Result<Self, ParseBoardError> {
+        let rows: Vec<&str> = s.trim().split('/').collect();
+        if rows.len() != 4 {
+            return Err(ParseBoardError::WrongRowCount(rows.len()));
+        }
+        if let Some(row) = rows.iter().find(|r| r.split(',').count() != 4) {
+            return Err(ParseBoardError::WrongCellCount(row.split(',').count()));
+        }
+        s.parse()
+    }
+
+    /// all four rows followed by all four columns
+    pub fn lines(&self) -> impl Iterator<Item = [Option<Tile>; 4]> {
+        self.rows().into_iter().chain(self.cols())
+    }
+}
+
+fn list_to_rows(arg: [i32; 16]) -> [[i32; 4]; 4] {
+    std::array::from_fn(|r| std::array::from_fn(|c| arg[r * 4 + c]))
+}
+
+/// `Board::nums` as one flat list of cells, which for 4x4 boards is the
+/// format saves have always used
+mod flat_cells {
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::Tile;
+
+    pub fn serialize<S: Serializer, const W: usize, const H: usize>(
+        nums: &[[Option<Tile>; W]; H],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        nums.as_flattened().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const W: usize, const H: usize>(
+        deserializer: D,
+    ) -> Result<[[Option<Tile>; W]; H], D::Error> {
+        let cells = Vec::<Option<Tile>>::deserialize(deserializer)?;
+        if cells.len() != W * H {
+            return Err(D::Error::invalid_length(
+                cells.len(),
+                &format!("{} cells", W * H).as_str(),
+            ));
+        }
+        Ok(std::array::from_fn(|r| {
+            std::array::from_fn(|c| cells[r * W + c])
+        }))
+    }
+}
+
+impl<const W: usize, const H: usize> Display for Board<W, H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for row in self.rows().iter() {
+            Self::print_row(f, row)?;
+            writeln!(f, "|")?;
+        }
+        Ok(())
+    }
+}
+
+const DEFAULT_UNDO_LIMIT: usize = 100;
+
+fn default_undo_limit() -> usize {
+    DEFAULT_UNDO_LIMIT
+}
+
+/// One line slid toward its start under the standard rules: the line after,
+/// and the merge score the move gained. `do_move` is this along every row or
+/// column, read from the edge the tiles slide toward.
+pub fn merge_line<const N: usize>(mut line: [Option<Tile>; N]) -> ([Option<Tile>; N], u64) {
+    let mut score = 0;
+    merge_cells(&mut line, false, |_, t| score += u64::from(t.as_u32()));
+    (line, score)
+}
+
+/// Slide and merge `line` toward index 0, calling `on_merge` with each
+/// merged tile's index and value. Returns whether anything moved.
+fn merge_cells(
+    line: &mut [Option<Tile>],
+    chain_merges: bool,
+    mut on_merge: impl FnMut(usize, Tile),
+) -> bool {
+    let len = line.len();
+    let mut changed = false;
+    for idx in 0..len.saturating_sub(1) {
+        for n in idx + 1..len {
+            if line[n].is_some() {
+                if line[idx] == line[n] {
+                    let merged = line[idx].unwrap().double();
+                    line[idx] = Some(merged);
+                    line[n] = None;
+                    on_merge(idx, merged);
+                    changed = true;
+                    // with chain_merges the merged tile goes on to meet the next one
+                    if !chain_merges {
+                        break;
+                    }
+                } else if line[idx].is_none() {
+                    line[idx] = line[n];
+                    line[n] = None;
+                    changed = true;
+                } else {
+                    break; // something in the way
+                }
+            }
+        }
+    }
+    changed
+}
+
+/// 64 bit FNV-1a, which unlike `DefaultHasher` is stable across Rust versions
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Games are equal when their boards are, regardless of RNG, score or history
+impl<const W: usize, const H: usize> PartialEq for Board<W, H> {
+    fn eq(&self, other: &Self) -> bool {
+        self.nums == other.nums
+    }
+}
+
+impl<const W: usize, const H: usize> fmt::Debug for Board<W, H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "GameState({})", self.to_compact())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseBoardError {
+    /// a board needs exactly 16 cells, or 4 per row in the compact format
+    WrongCellCount(usize),
+    /// the compact format needs exactly 4 rows
+    WrongRowCount(usize),
+    /// a cell that isn't empty (`.` or `0`) or a power of two >= 2
+    InvalidTile(String),
+}
+
+impl Display for ParseBoardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseBoardError::WrongCellCount(n) => write!(f, "wrong number of cells: {n}"),
+            ParseBoardError::WrongRowCount(n) => write!(f, "expected 4 rows, found {n}"),
+            ParseBoardError::InvalidTile(t) => write!(f, "invalid tile {t:?}"),
+        }
+    }
+}
+
+impl Error for ParseBoardError {}
+
+/// Parses 16 cells in row-major order, separated by commas, slashes or
+/// whitespace. Empty cells are `.` or `0`, e.g. `2,4,.,8/16,.,.,2/...`
+impl FromStr for GameState {
+    type Err = ParseBoardError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let cells = s
+            .split(|c: char| c == ',' || c == '/' || c.is_whitespace())
+            .filter(|c| !c.is_empty())
+            .map(|c| match c {
+                "." | "0" => Ok(-1),
+                c => match c.parse::<i32>() {
+                    Ok(n) if n >= 2 && n.count_ones() == 1 => Ok(n),
+                    _ => Err(ParseBoardError::InvalidTile(c.to_string())),
+                },
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let cells: [i32; 16] = cells
+            .try_into()
+            .map_err(|c: Vec<i32>| ParseBoardError::WrongCellCount(c.len()))?;
+
+        Ok(GameState::from_list(cells))
+    }
+}
+
+// which power of two. NonZero because two is the lowest. Ordering by exponent
+// is the same as ordering by value
+#[derive(
+    Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+pub struct Tile(NonZeroU32);
+
+impl Tile {
+    pub const TWO: Tile = Tile(NonZeroU32::new(1).unwrap());
+    pub const FOUR: Tile = Tile::TWO.double();
+
+    /// `None` unless `1 <= exponent <= 31`
+    pub fn from_exponent(exponent: u32) -> Option<Tile> {
+        if exponent < 32 {
+            NonZeroU32::new(exponent).map(Tile)
+        } else {
+            None
+        }
+    }
+
+    const fn double(&self) -> Tile {
+        Tile(NonZeroU32::new(self.0.get() + 1).unwrap())
+    }
+
+    pub fn as_u32(&self) -> u32 {
+        2_u32.pow(self.0.get())
+    }
+
+    pub fn exponent(&self) -> u32 {
+        self.0.get()
+    }
+
+    /// Cell color as rgb, the same palette as the web version's `value_N`
+    /// classes. Tiles past the palette step around the color wheel by the
+    /// golden angle, so no two of them look alike.
+    pub fn background(&self) -> (u8, u8, u8) {
+        let e = self.exponent() as usize;
+        if let Some(&rgb) = TILE_BACKGROUNDS.get(e - 1) {
+            return rgb;
+        }
+        let hue = (e as f64 * 137.508) % 360.;
+        // alternate lightness too, so neighbours differ even where hues are close
+        let lightness = if e.is_multiple_of(2) { 0.3 } else { 0.4 };
+        hsl_to_rgb(hue, 0.7, lightness)
+    }
+
+    /// `background` as a css `#rrggbb`
+    pub fn background_hex(&self) -> String {
+        let (r, g, b) = self.background();
+        format!("#{r:02x}{g:02x}{b:02x}")
+    }
+
+    /// Whether the web version has a `value_N` class for this tile, or needs
+    /// `background_hex` set inline
+    pub fn has_palette_color(&self) -> bool {
+        self.exponent() as usize <= TILE_BACKGROUNDS.len()
+    }
+}
+
+/// hue in degrees, saturation and lightness in 0..=1
+fn hsl_to_rgb(hue: f64, saturation: f64, lightness: f64) -> (u8, u8, u8) {
+    let chroma = (1. - (2. * lightness - 1.).abs()) * saturation;
+    let x = chroma * (1. - ((hue / 60.) % 2. - 1.).abs());
+    let (r, g, b) = match (hue / 60.) as u32 {
+        0 => (chroma, x, 0.),
+        1 => (x, chroma, 0.),
+        2 => (0., chroma, x),
+        3 => (0., x, chroma),
+        4 => (x, 0., chroma),
+        _ => (chroma, 0., x),
+    };
+    let m = lightness - chroma / 2.;
+    let channel = |c: f64| ((c + m) * 255.).round() as u8;
+    (channel(r), channel(g), channel(b))
+}
+
+const TILE_BACKGROUNDS: [(u8, u8, u8); 14] = [
+    (0x14, 0x40, 0x6b),
+    (0x57, 0x77, 0xcf),
+    (0x3c, 0x99, 0xc4),
+    (0x25, 0x7a, 0x7a),
+    (0x0b, 0x5f, 0x16),
+    (0x3f, 0x5f, 0x0b),
+    (0xbd, 0x5d, 0xa0),
+    (0xad, 0x0c, 0x95),
+    (0x8d, 0x41, 0x8d),
+    (0x4e, 0x2a, 0xa1),
+    (0x32, 0x0b, 0x5f),
+    (0x5f, 0x3d, 0x0b),
+    (0x5f, 0x23, 0x0b),
+    (0x5f, 0x0b, 0x0b),
+];
+
+/// A tile as the number shown on it. `Tile` serializes as its exponent, which
+/// keeps saves small; this is for JSON that people or front ends read.
+#[derive(
+    Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+#[serde(try_from = "u32", into = "u32")]
+pub struct TileValue(u32);
+
+impl TileValue {
+    pub fn get(&self) -> u32 {
+        self.0
+    }
+}
+
+impl From<Tile> for TileValue {
+    fn from(t: Tile) -> Self {
+        TileValue(t.as_u32())
+    }
+}
+
+impl From<TileValue> for Tile {
+    fn from(v: TileValue) -> Self {
+        Tile(NonZeroU32::new(v.0.ilog2()).unwrap())
+    }
+}
+
+impl From<TileValue> for u32 {
+    fn from(v: TileValue) -> Self {
+        v.0
+    }
+}
+
+impl TryFrom<u32> for TileValue {
+    type Error = String;
+
+    /// only powers of two from 2 up are tiles
+    fn try_from(n: u32) -> Result<Self, Self::Error> {
+        if n >= 2 && n.is_power_of_two() {
+            Ok(TileValue(n))
+        } else {
+            Err(format!("{n} is not a tile value"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        merge_line, Board, Direction, GameState, InvalidMove, InvalidSpawnTable, Merge,
+        MovePreview, ParseBoardError, Rules, SpawnTable, Tile, TileValue, UndoPolicy,
+    };
+
+    // | 128 | 64  | 32  |  8  |
+    // |  8  |  4  |  8  |  4  |
+    // |     |     |     |     |
+    // |     |     |     |     |
+    #[test]
+    fn testcase1() {
+        let gs = GameState::from_list([128, 64, 32, 8, 8, 4, 8, 4, -1, -1, -1, -1, -1, -1, -1, -1]);
+        assert!(!gs.can_move(Direction::Right));
+    }
+
+    #[test]
+    fn win_stops_game() {
+        let mut gs = GameState::from_list([
+            1024, 1024, -1, -1, 2, 4, 2, -1, -1, -1, -1, -1, -1, -1, -1, -1,
+        ])
+        .with_rules(Rules {
+            win_stops_game: true,
+            ..Rules::default()
+        });
+        assert!(!gs.finished());
+
+        gs.do_move(Direction::Left);
+        assert!(gs.won());
+        assert!(gs.finished());
+
+        let before = gs.rows();
+        gs.do_move(Direction::Right);
+        assert!(gs.rows() == before);
+    }
+
+    #[test]
+    fn parse_board() {
+        let gs: GameState = "2 4 . 8  16 0 0 2  . . . .  . . . 2048".parse().unwrap();
+        assert!(
+            gs.rows()
+                == GameState::from_list([
+                    2, 4, -1, 8, 16, -1, -1, 2, -1, -1, -1, -1, -1, -1, -1, 2048
+                ])
+                .rows()
+        );
+
+        assert_eq!(
+            "2,4,.,8".parse::<GameState>().err(),
+            Some(ParseBoardError::WrongCellCount(4))
+        );
+        assert_eq!(
+            "3,.,.,./.,.,.,./.,.,.,./.,.,.,.".parse::<GameState>().err(),
+            Some(ParseBoardError::InvalidTile("3".to_string()))
+        );
+    }
+
+    #[test]
+    fn deterministic_spawns() {
+        let mut gs =
+            GameState::from_list([2, -1, 2, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1]);
+
+        gs.do_move(Direction::Right);
+        assert_eq!(gs.spawn_first_open(), Some(0));
+        gs.do_move(Direction::Down);
+        assert_eq!(gs.spawn_first_open(), Some(0));
+        gs.spawn_at(1, Tile::FOUR);
+
+        assert!(
+            gs.rows()
+                == GameState::from_list([
+                    2, 4, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, 2, -1, -1, 4,
+                ])
+                .rows()
+        );
+    }
+
+    #[test]
+    fn move_count_skips_noops() {
+        let mut gs = GameState::from_list([
+            2, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1,
+        ]);
+        gs.do_move(Direction::Left);
+        assert_eq!(gs.move_count(), 0);
+        gs.do_move(Direction::Right);
+        gs.do_move(Direction::Down);
+        assert_eq!(gs.move_count(), 2);
+    }
+
+    #[test]
+    fn apply_illegal_is_noop() {
+        let mut gs =
+            GameState::from_list([2, 4, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1]);
+        let before = gs.rows();
+        assert!(!gs.apply(Direction::Left));
+        assert!(!gs.apply(Direction::Up));
+        assert!(gs.rows() == before);
+        assert_eq!(gs.move_count(), 0);
+
+        assert!(gs.apply(Direction::Right));
+        assert_eq!(gs.rows().iter().flatten().flatten().count(), 3);
+    }
+
+    #[test]
+    fn merge_score_counts_merged_tiles() {
+        let mut gs =
+            GameState::from_list([2, 2, 4, 4, 8, -1, 8, -1, -1, -1, -1, -1, -1, -1, -1, -1]);
+        gs.do_move(Direction::Left);
+        assert_eq!(gs.merge_score(), 4 + 8 + 16);
+    }
+
+    #[test]
+    fn possible_spawns() {
+        let gs =
+            GameState::from_list([2, 4, 8, -1, 16, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1]);
+        assert_eq!(gs.possible_spawns().count(), 2 * 12);
+        let total: f64 = gs.possible_spawns().map(|(_, _, p)| p).sum();
+        assert!((total - 1.0).abs() < 1e-9);
+
+        let full = GameState::from_list([2; 16]);
+        assert_eq!(full.possible_spawns().count(), 0);
+    }
+
+    #[test]
+    fn compact_round_trip() {
+        let gs = GameState::from_list([
+            2, 4, -1, 8, 16, -1, -1, 2, -1, -1, -1, -1, 1024, -1, -1, 2048,
+        ]);
+        let compact = gs.to_compact();
+        assert_eq!(compact, "2,4,.,8/16,.,.,2/.,.,.,./1024,.,.,2048");
+        assert!(GameState::from_compact(&compact).unwrap().rows() == gs.rows());
+
+        assert_eq!(
+            GameState::from_compact("2,4,.,8/16,.,.,2").err(),
+            Some(ParseBoardError::WrongRowCount(2))
+        );
+    }
+
+    #[test]
+    fn undo_replays_identically() {
+        let mut gs = GameState::new_from_seed(7);
+        let start = gs.rows();
+
+        assert!(gs.apply(Direction::Left) || gs.apply(Direction::Right));
+        let first = gs.rows();
+        let mut second = None;
+        for d in [Direction::Up, Direction::Down] {
+            if gs.apply(d) {
+                second = Some((d, gs.rows()));
+                break;
+            }
+        }
+        let (second_dir, second) = second.unwrap();
+
+        assert!(gs.undo());
+        assert!(gs.undo());
+        assert!(!gs.undo());
+        assert!(gs.rows() == start);
+
+        assert!(gs.redo());
+        assert!(gs.rows() == first);
+        assert!(gs.undo());
+
+        assert!(gs.apply(Direction::Left) || gs.apply(Direction::Right));
+        assert!(gs.rows() == first);
+        assert!(!gs.can_redo());
+        assert!(gs.apply(second_dir));
+        assert!(gs.rows() == second);
+    }
+
+    #[test]
+    fn tile_ordering() {
+        assert!(Tile::TWO < Tile::FOUR);
+
+        let gs =
+            GameState::from_list([8, 2, 1024, 4, 2, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1]);
+        let mut tiles: Vec<Tile> = gs.rows().iter().flatten().flatten().copied().collect();
+        tiles.sort();
+        assert_eq!(
+            tiles.iter().map(Tile::as_u32).collect::<Vec<_>>(),
+            [2, 2, 4, 8, 1024]
+        );
+    }
+
+    #[test]
+    fn custom_spawn_table() {
+        let eight = Tile::from_exponent(3).unwrap();
+        let mut gs =
+            GameState::new_with_spawn_table(3, SpawnTable::new(vec![(eight, 1.0)]).unwrap());
+        for _ in 0..10 {
+            gs.apply(Direction::Left);
+            gs.apply(Direction::Up);
+        }
+        assert!(gs
+            .rows()
+            .iter()
+            .flatten()
+            .flatten()
+            .all(|t| t.as_u32() % 8 == 0));
+
+        assert_eq!(SpawnTable::new(vec![]), Err(InvalidSpawnTable::Empty));
+        assert_eq!(
+            SpawnTable::new(vec![(Tile::TWO, 1.0), (Tile::FOUR, 0.0)]),
+            Err(InvalidSpawnTable::BadWeight(0.0))
+        );
+    }
+
+    #[test]
+    fn same_tiles_ignores_position() {
+        let a = GameState::from_list([2, 4, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, 8]);
+        let b = GameState::from_list([-1, -1, -1, 8, -1, -1, -1, -1, -1, -1, -1, -1, 4, -1, -1, 2]);
+        let c = GameState::from_list([2, 2, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, 8]);
+
+        assert!(a.same_tiles(&b));
+        assert_ne!(a, b);
+        assert!(!a.same_tiles(&c));
+        assert!(a.same_tiles(&a.clone()));
+        assert_eq!(a, a.clone());
+    }
+
+    #[test]
+    fn largest_tiles() {
+        let gs = GameState::from_list([8, 2, -1, 64, -1, 8, -1, -1, 32, -1, -1, -1, -1, -1, 4, -1]);
+        let top: Vec<u32> = gs.largest_n_tiles(3).iter().map(Tile::as_u32).collect();
+        assert_eq!(top, [64, 32, 8]);
+        assert_eq!(gs.largest_n_tiles(10).len(), 6);
+        assert!(gs.largest_n_tiles(0).is_empty());
+    }
+
+    #[test]
+    fn validity() {
+        let gs = GameState::new_from_seed(1);
+        assert!(gs.is_valid());
+        assert!(GameState::from_list([2048; 16]).is_valid());
+
+        // tiles are stored as exponents, and 2^40 doesn't fit
+        let json = serde_json::to_string(&gs).unwrap();
+        let corrupted = json.replacen("null", "40", 1);
+        let parsed: GameState = serde_json::from_str(&corrupted).unwrap();
+        assert!(!parsed.is_valid(), "{corrupted}");
+
+        let empty_table = json.replace(
+            &serde_json::to_string(&SpawnTable::default()).unwrap(),
+            "[]",
+        );
+        let parsed: GameState = serde_json::from_str(&empty_table).unwrap();
+        assert!(!parsed.is_valid(), "{empty_table}");
+
+        let mut over_limit = GameState::new_from_seed(1);
+        over_limit.apply(over_limit.valid_moves()[0]);
+        let over_limit = over_limit.with_rules(Rules {
+            move_limit: Some(0),
+            ..Rules::default()
+        });
+        assert!(!over_limit.is_valid());
+    }
+
+    #[test]
+    fn lines_are_rows_then_cols() {
+        let gs =
+            GameState::from_list([2, 4, -1, -1, 8, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, 16]);
+        let lines: Vec<_> = gs.lines().collect();
+        assert_eq!(lines.len(), 8);
+        assert_eq!(lines[..4], gs.rows());
+        assert_eq!(lines[4..], gs.cols());
+
+        let first_col: Vec<_> = gs.cols()[0].iter().map(|t| t.map(|t| t.as_u32())).collect();
+        assert_eq!(first_col, [Some(2), Some(8), None, None]);
+    }
+
+    #[test]
+    fn extra_spawns_per_move() {
+        let rules = Rules {
+            spawns_per_move: 2,
+            ..Rules::default()
+        };
+        let mut gs = GameState::new_from_seed(8).with_rules(rules);
+        let tiles = |gs: &GameState| 16 - gs.empty_count();
+        while let Some(&dir) = gs.valid_moves().first() {
+            let after_move = tiles(&gs) - gs.merge_count(dir);
+            let spawns = gs.apply_spawning(dir).unwrap();
+            assert_eq!(spawns.len(), 2.min(16 - after_move));
+            assert_eq!(tiles(&gs), after_move + spawns.len());
+        }
+
+        let mut sparse = GameState::from_list_seeded(
+            [
+                2, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1,
+            ],
+            1,
+        )
+        .with_rules(Rules {
+            spawns_per_move: 2,
+            ..Rules::default()
+        });
+        assert!(sparse.apply(Direction::Right));
+        assert_eq!(tiles(&sparse), 3);
+    }
+
+    #[test]
+    fn chain_merges() {
+        let row = [2, 2, 2, 2, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1];
+
+        let mut standard = GameState::from_list(row);
+        standard.do_move(Direction::Left);
+        assert_eq!(standard.to_compact(), "4,4,.,./.,.,.,./.,.,.,./.,.,.,.");
+        assert_eq!(standard.merge_score(), 8);
+
+        let mut chained = GameState::from_list(row).with_rules(Rules {
+            chain_merges: true,
+            ..Rules::default()
+        });
+        chained.do_move(Direction::Left);
+        // the first 4 is made before the second pair merges, so it meets a 2
+        assert_eq!(chained.to_compact(), "4,4,.,./.,.,.,./.,.,.,./.,.,.,.");
+        assert_eq!(chained.merge_score(), 8);
+
+        let chain = |row| {
+            let mut gs = GameState::from_list(row).with_rules(Rules {
+                chain_merges: true,
+                ..Rules::default()
+            });
+            gs.do_move(Direction::Left);
+            (gs.to_compact(), gs.merge_score())
+        };
+        let growing = [2, 2, 4, 8, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1];
+        assert_eq!(
+            chain(growing),
+            ("16,.,.,./.,.,.,./.,.,.,./.,.,.,.".into(), 28)
+        );
+        let mut standard = GameState::from_list(growing);
+        standard.do_move(Direction::Left);
+        assert_eq!(standard.to_compact(), "4,4,8,./.,.,.,./.,.,.,./.,.,.,.");
+
+        // a merge never reaches back into a cell that's already done
+        let behind = [4, 2, 2, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1];
+        assert_eq!(chain(behind), ("4,4,.,./.,.,.,./.,.,.,./.,.,.,.".into(), 4));
+    }
+
+    #[test]
+    fn move_limit() {
+        let mut gs = GameState::new_from_seed(5).with_rules(Rules {
+            move_limit: Some(2),
+            ..Rules::default()
+        });
+        assert_eq!(gs.moves_remaining(), Some(2));
+
+        let played = [
+            Direction::Left,
+            Direction::Right,
+            Direction::Up,
+            Direction::Down,
+        ]
+        .repeat(2)
+        .into_iter()
+        .filter(|&d| gs.apply(d))
+        .count();
+        assert_eq!(played, 2);
+        assert_eq!(gs.moves_remaining(), Some(0));
+        assert!(gs.finished());
+
+        let before = gs.clone();
+        for d in [
+            Direction::Left,
+            Direction::Right,
+            Direction::Up,
+            Direction::Down,
+        ] {
+            assert!(!gs.apply(d));
+            gs.do_move(d);
+        }
+        assert_eq!(gs, before);
+        assert_eq!(gs.move_count(), 2);
+    }
+
+    #[test]
+    fn fingerprint_is_stable() {
+        let mut gs = GameState::new_from_seed(11);
+        assert_eq!(gs.fingerprint(), gs.clone().fingerprint());
+
+        let before = gs.fingerprint();
+        gs.apply(Direction::Left);
+        gs.apply(Direction::Up);
+        assert_ne!(gs.fingerprint(), before);
+
+        assert_eq!(super::fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+    }
+
+    #[test]
+    fn fill_fraction() {
+        let mut empty = GameState::from_list([-1; 16]);
+        assert_eq!(empty.empty_count(), 16);
+        assert_eq!(empty.fill_fraction(), 0.);
+
+        for _ in 0..8 {
+            empty.spawn_first_open();
+        }
+        assert_eq!(empty.fill_fraction(), 0.5);
+
+        assert_eq!(GameState::from_list([2; 16]).fill_fraction(), 1.);
+    }
+
+    #[test]
+    fn undo_policies() {
+        let play = |policy| {
+            let mut gs = GameState::new_from_seed(4).with_rules(Rules {
+                undo_policy: policy,
+                ..Rules::default()
+            });
+            for _ in 0..3 {
+                gs.apply(gs.valid_moves()[0]);
+            }
+            gs
+        };
+
+        let mut limited = play(UndoPolicy::Limited(1));
+        assert_eq!(limited.undos_remaining(), Some(1));
+        assert!(limited.undo());
+        assert_eq!(limited.undos_remaining(), Some(0));
+        assert!(!limited.undo());
+
+        let mut penalized = play(UndoPolicy::Penalized(4));
+        let (score, merge_score) = (penalized.score(), penalized.merge_score());
+        assert!(penalized.undo());
+        assert!(penalized.redo());
+        assert_eq!(
+            (penalized.score(), penalized.merge_score()),
+            (score, merge_score)
+        );
+        assert_eq!(penalized.undo_penalty(), 4);
+        assert_eq!(penalized.effective_score(), score - 4);
+
+        let mut free = play(UndoPolicy::Free);
+        assert!(free.undo() && free.undo());
+        assert_eq!(free.undos_remaining(), None);
+        assert_eq!(free.effective_score(), free.score());
+    }
+
+    #[test]
+    fn undo_limit() {
+        let mut gs = GameState::new_from_seed(2);
+        gs.set_undo_limit(3);
+
+        let mut boards = vec![gs.clone()];
+        while boards.len() < 6 {
+            for d in [
+                Direction::Left,
+                Direction::Down,
+                Direction::Right,
+                Direction::Up,
+            ] {
+                if boards.len() < 6 && gs.apply(d) {
+                    boards.push(gs.clone());
+                }
+            }
+        }
+        assert_eq!(gs.history.len(), 3);
+
+        for expected in boards[2..5].iter().rev() {
+            assert!(gs.undo());
+            assert_eq!(&gs, expected);
+        }
+        assert!(!gs.can_undo());
+    }
+
+    #[test]
+    fn reseed_replays() {
+        let gs = GameState::new_from_entropy();
+        assert!(gs.seed().is_some());
+        assert_eq!(GameState::from_list([-1; 16]).seed(), None);
+
+        let play = |mut gs: GameState| {
+            gs.reseed(99);
+            for d in [Direction::Left, Direction::Up, Direction::Right].repeat(5) {
+                gs.apply(d);
+            }
+            gs
+        };
+        let a = play(gs.clone());
+        let b = play(gs);
+        assert_eq!(a.seed(), Some(99));
+        assert_eq!(a, b);
+        assert_eq!(a.merge_score(), b.merge_score());
+    }
+
+    // plays random games to shake out panics and broken invariants in the
+    // move/spawn/score code; slow in debug builds, run with `--ignored`
+    #[test]
+    #[ignore]
+    fn random_game_soak() {
+        use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+        for seed in 0..20_000 {
+            let mut gs = GameState::new_from_seed(seed);
+            let mut picker = StdRng::seed_from_u64(!seed);
+            loop {
+                let moves = gs.valid_moves();
+                assert_eq!(gs.lost(), moves.is_empty(), "seed {seed}: {gs:?}");
+                let Some(&dir) = moves.choose(&mut picker) else {
+                    break;
+                };
+
+                let (score, merge_score) = (gs.score(), gs.merge_score());
+                let total = gs.total_value();
+                assert!(gs.apply(dir), "seed {seed}: {dir:?} on {gs:?}");
+                assert!(
+                    matches!(gs.total_value() - total, 2 | 4),
+                    "seed {seed}: {dir:?} took the total from {total} to {}",
+                    gs.total_value()
+                );
+                assert!(gs.score() >= score, "seed {seed}: score went down");
+                assert!(
+                    gs.merge_score() >= merge_score,
+                    "seed {seed}: merge score went down"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn biggest_tile_a_move_can_make() {
+        // Left and Right both merge the 4s, but only Up and Down bring the 32s
+        // together; Up comes first
+        #[rustfmt::skip]
+        let gs = GameState::from_list([
+            32, 4,  4,  -1,
+            32, -1, -1, -1,
+            2,  -1, -1, -1,
+            -1, -1, -1, 16,
+        ]);
+        assert_eq!(gs.largest_merge_result(), Some((Direction::Up, 64)));
+        // the most points isn't always the biggest tile
+        #[rustfmt::skip]
+        let many = GameState::from_list([
+            16, 16, -1, 32,
+            4,  2,  -1, 32,
+            16, 16, -1, -1,
+            2,  2,  -1, -1,
+        ]);
+        assert_eq!(many.best_merge_cell(), Some((Direction::Left, 68)));
+        assert_eq!(many.largest_merge_result(), Some((Direction::Up, 64)));
+
+        let stuck =
+            GameState::from_list([2, 4, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1]);
+        assert_eq!(stuck.largest_merge_result(), None);
+    }
+
+    #[test]
+    fn random_moves_come_from_the_game() {
+        let board = [2, 4, -1, -1, 8, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, 4];
+        // the same board in different games doesn't always get the same move
+        let picks: Vec<_> = (0..32)
+            .map(|seed| {
+                GameState::from_list_seeded(board, seed)
+                    .pick_random_move()
+                    .unwrap()
+            })
+            .collect();
+        assert!(picks.iter().any(|&d| d != picks[0]), "{picks:?}");
+
+        // and picking doesn't use up spawns
+        let mut picked = GameState::from_list_seeded(board, 3);
+        let mut plain = picked.clone();
+        let dir = picked.pick_random_move().unwrap();
+        assert_eq!(picked.pick_random_move(), Some(dir));
+        assert_eq!(picked.apply_spawning(dir), plain.apply_spawning(dir));
+
+        assert_eq!(
+            GameState::from_list([2, 4, 2, 4, 4, 2, 4, 2, 2, 4, 2, 4, 4, 2, 4, 2])
+                .pick_random_move(),
+            None
+        );
+    }
+
+    #[test]
+    fn illegal_moves_dont_spawn() {
+        let mut gs = GameState::from_list_seeded(
+            [2, 4, -1, -1, 8, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+            5,
+        );
+        let before = gs.clone();
+        assert!(!gs.apply(Direction::Left));
+        assert!(!gs.apply(Direction::Up));
+        assert_eq!(gs.apply_spawning(Direction::Left), None);
+        assert_eq!(gs, before);
+        assert_eq!(gs.empty_count(), 13);
+        assert_eq!(gs.move_count(), 0);
+        assert!(!gs.can_undo());
+
+        // and the spawn that comes with the next legal move is the one it
+        // would have been anyway
+        let mut fresh = before.clone();
+        assert!(gs.apply(Direction::Right));
+        assert!(fresh.apply(Direction::Right));
+        assert_eq!(gs, fresh);
+    }
+
+    #[test]
+    fn total_value_only_grows_by_the_spawn() {
+        let gs = GameState::from_list([2, 2, 4, -1, 8, -1, 8, -1, -1, -1, -1, -1, -1, -1, -1, 16]);
+        assert_eq!(gs.total_value(), 40);
+        assert_eq!(gs.preview_move(Direction::Left).unwrap().total_value(), 40);
+
+        for seed in 0..20 {
+            let mut gs = GameState::new_from_seed(seed);
+            while let Some(&dir) = gs.valid_moves().first() {
+                let total = gs.total_value();
+                let spawns = gs.apply_spawning(dir).unwrap();
+                let spawned: u64 = spawns.iter().map(|(_, t)| u64::from(t.as_u32())).sum();
+                assert!(matches!(spawned, 2 | 4), "seed {seed}");
+                assert_eq!(gs.total_value(), total + spawned, "seed {seed}");
+            }
+        }
+    }
+
+    #[test]
+    fn apply_all_then_report() {
+        let mut gs = GameState::from_list_seeded(
+            [2, 2, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+            6,
+        );
+        let mut plan = vec![Direction::Left, Direction::Right].into_iter();
+        let summary = gs.apply_all_then_report(|_| plan.next());
+        assert_eq!(summary.moves, 2);
+        assert_eq!(summary.max_tile, gs.max());
+        assert_eq!(summary.merge_score, gs.merge_score());
+        assert!(summary.merge_score >= 4);
+        assert!(!summary.won);
+
+        // an illegal choice stops the run rather than looping forever
+        let mut asked = 0;
+        let mut full = GameState::from_list([2, 4, 2, 4, 4, 2, 4, 2, 2, 4, 2, 4, 4, 2, 4, 2]);
+        full.apply_all_then_report(|_| {
+            asked += 1;
+            Some(Direction::Up)
+        });
+        assert_eq!(asked, 0);
+    }
+
+    #[test]
+    fn open_tiles_are_picked_uniformly() {
+        let mut gs =
+            GameState::from_list_seeded([2, -1, 4, 8, -1, 2, 4, 8, 2, -1, 4, 8, 2, 4, 8, -1], 12);
+        let mut picks = [0; 16];
+        for _ in 0..40_000 {
+            picks[gs.random_open_tile().unwrap()] += 1;
+        }
+        for (i, &n) in picks.iter().enumerate() {
+            if [1, 4, 9, 15].contains(&i) {
+                assert!((9_500..10_500).contains(&n), "cell {i} picked {n} times");
+            } else {
+                assert_eq!(n, 0, "cell {i} isn't empty");
+            }
+        }
+        assert_eq!(GameState::from_list([2; 16]).random_open_tile(), None);
+    }
+
+    #[test]
+    fn spawn_lands_in_an_empty_cell() {
+        for seed in 0..50 {
+            let mut gs = GameState::new_from_seed(seed);
+            while let Some(&dir) = gs.valid_moves().first() {
+                let mut moved = gs.preview_move(dir).unwrap();
+                let [(index, tile)] = gs.apply_spawning(dir).unwrap()[..] else {
+                    panic!("one spawn per move by default");
+                };
+                assert!(moved.rows().concat()[index].is_none(), "seed {seed}");
+                moved.spawn_at(index, tile);
+                assert_eq!(moved, gs);
+            }
+        }
+    }
+
+    // 4 wide and 5 tall, so a mix-up between width and height in the index
+    // math lands tiles in the wrong place or out of bounds
+    #[test]
+    fn rectangular_board_moves() {
+        let start = [
+            [2, -1, -1, 2],
+            [-1, -1, -1, -1],
+            [4, -1, -1, -1],
+            [-1, -1, -1, -1],
+            [4, -1, 8, -1],
+        ];
+        let moved = |dir| {
+            let mut b = Board::<4, 5>::from_rows(start);
+            b.do_move(dir);
+            b.rows().map(|r| r.map(|t| t.map_or(0, |t| t.as_u32())))
+        };
+
+        assert_eq!(
+            moved(Direction::Down),
+            [[0; 4], [0; 4], [0; 4], [2, 0, 0, 0], [8, 0, 8, 2]]
+        );
+        assert_eq!(
+            moved(Direction::Up),
+            [[2, 0, 8, 2], [8, 0, 0, 0], [0; 4], [0; 4], [0; 4]]
+        );
+        assert_eq!(
+            moved(Direction::Left),
+            [[4, 0, 0, 0], [0; 4], [4, 0, 0, 0], [0; 4], [4, 8, 0, 0]]
+        );
+        assert_eq!(
+            moved(Direction::Right),
+            [[0, 0, 0, 4], [0; 4], [0, 0, 0, 4], [0; 4], [0, 0, 4, 8]]
+        );
+
+        let mut game = Board::<4, 5>::new_from_seed(7);
+        while let Some(&dir) = game.valid_moves().first() {
+            game.apply(dir);
+        }
+        assert_eq!(game.empty_count(), 0);
+
+        let json = serde_json::to_string(&game).unwrap();
+        let parsed: Board<4, 5> = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, game);
+        assert!(serde_json::from_str::<GameState>(&json).is_err());
+    }
+
+    #[test]
+    fn restart_reproduces_the_start() {
+        let fresh = GameState::new_from_entropy();
+        let mut gs = fresh.clone();
+        for d in [Direction::Left, Direction::Up, Direction::Right] {
+            gs.apply(d);
+        }
+        assert!(gs.restart());
+        assert_eq!(gs, fresh);
+        assert_eq!(gs.move_count(), 0);
+        assert!(!gs.can_undo());
+
+        // and it survives a save
+        let mut loaded: GameState =
+            serde_json::from_str(&serde_json::to_string(&gs).unwrap()).unwrap();
+        loaded.apply(loaded.valid_moves()[0]);
+        assert!(loaded.restart());
+        assert_eq!(loaded, fresh);
+
+        assert!(!GameState::from_list([2; 16]).restart());
+    }
+
+    // a move is legal exactly when it changes the board, on boards crowded
+    // enough to have plenty of blocked and mergeable lines
+    #[test]
+    fn can_move_agrees_with_do_move() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        fn check<const W: usize, const H: usize>(rng: &mut StdRng) {
+            let rows = [[0; W]; H].map(|row| {
+                row.map(|_| match rng.gen_range(0..6) {
+                    0 => -1,
+                    e => 1 << e,
+                })
+            });
+            let gs = Board::<W, H>::from_rows(rows);
+            for d in Direction::all() {
+                let mut moved = gs.clone();
+                moved.do_move(d);
+                assert_eq!(gs.can_move(d), moved != gs, "{d:?} on {gs:?}");
+            }
+        }
+
+        let mut rng = StdRng::seed_from_u64(153);
+        for _ in 0..2000 {
+            check::<4, 4>(&mut rng);
+            check::<4, 5>(&mut rng);
+            check::<3, 2>(&mut rng);
+        }
+    }
+
+    #[test]
+    fn undo_out_of_game_over() {
+        let mut gs = GameState::new_from_seed(31);
+        while let Some(&d) = gs.valid_moves().first() {
+            gs.apply(d);
+        }
+        assert!(gs.lost() && gs.finished());
+        let lost_at = gs.move_count();
+
+        assert!(gs.undo());
+        assert!(!gs.lost() && !gs.finished());
+        assert_eq!(gs.move_count(), lost_at - 1);
+        assert!(gs.valid_moves().iter().any(|&d| gs.clone().apply(d)));
+        assert!(gs.redo());
+        assert!(gs.lost());
+
+        let mut won =
+            GameState::from_list([2, 2, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1])
+                .with_rules(Rules {
+                    target: 4,
+                    win_stops_game: true,
+                    ..Rules::default()
+                });
+        assert!(won.apply(Direction::Left));
+        assert!(won.finished());
+        assert!(!won.apply(Direction::Right));
+        assert!(won.undo());
+        assert!(!won.won() && !won.finished());
+        assert!(won.apply(Direction::Right));
+    }
+
+    #[test]
+    fn no_op_moves_spawn_nothing() {
+        // the top row is packed with no merges, so up, left and right do
+        // nothing while down is the only real move
+        let mut gs = GameState::from_list_seeded(
+            [2, 4, 2, 4, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+            6,
+        );
+        assert!(gs.apply(Direction::Down));
+        assert!(gs.undo());
+
+        for d in [Direction::Up, Direction::Left, Direction::Right] {
+            assert_eq!(gs.apply_spawning(d), None);
+            assert_eq!(gs.empty_count(), 12);
+            assert_eq!(gs.move_count(), 0);
+            assert!(!gs.can_undo(), "{d:?} pushed an undo snapshot");
+        }
+        // and the undone move is still there to redo
+        assert!(gs.redo());
+
+        let mut chained =
+            GameState::from_list([4, 2, 2, -1, 8, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1])
+                .with_rules(Rules {
+                    chain_merges: true,
+                    ..Rules::default()
+                });
+        assert_eq!(chained.apply_spawning(Direction::Up), None);
+        assert!(chained.apply(Direction::Left));
+        assert_eq!(chained.rows()[0][..2], [Tile::from_exponent(2); 2]);
+    }
+
+    #[test]
+    fn scores_saturate() {
+        let mut gs =
+            GameState::from_list([2, 2, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1]);
+        gs.merge_score = u64::MAX - 2;
+        gs.do_move(Direction::Left);
+        assert_eq!(gs.merge_score(), u64::MAX);
+
+        gs.spawn_at(1, Tile::TWO);
+        gs.do_move(Direction::Right);
+        assert_eq!(gs.merge_score(), u64::MAX);
+
+        let mut penalized = GameState::from_list([1 << 30; 16]).with_rules(Rules {
+            undo_policy: UndoPolicy::Penalized(u64::MAX / 2),
+            ..Rules::default()
+        });
+        penalized.undos_used = 3;
+        assert_eq!(penalized.undo_penalty(), u64::MAX);
+        assert_eq!(penalized.effective_score(), 0);
+        assert_eq!(penalized.score(), 16 << 30);
+    }
+
+    #[test]
+    fn values_serialize_as_numbers() {
+        let gs = Board::<2, 2>::from_rows([[2, -1], [1024, 4]]);
+        let json = serde_json::to_string(&gs.values()).unwrap();
+        assert_eq!(json, "[[2,null],[1024,4]]");
+
+        let parsed: [[Option<TileValue>; 2]; 2] = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.map(|row| row.map(|v| v.map(Tile::from))), gs.rows());
+        assert_eq!(Tile::from(parsed[1][0].unwrap()).exponent(), 10);
+
+        assert!(serde_json::from_str::<TileValue>("3").is_err());
+        assert!(serde_json::from_str::<TileValue>("1").is_err());
+        assert!(serde_json::from_str::<TileValue>("0").is_err());
+    }
+
+    #[test]
+    fn previews_for_every_direction() {
+        let gs = GameState::from_list([4, 2, 2, -1, 8, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1]);
+        let merge = Some(MovePreview {
+            merge_score: 4,
+            merges: 1,
+            empties_after: 13,
+        });
+        assert_eq!(
+            gs.move_previews(),
+            [
+                None,
+                Some(MovePreview {
+                    merge_score: 0,
+                    merges: 0,
+                    empties_after: 12,
+                }),
+                merge,
+                merge,
+            ]
+        );
+        for (d, p) in Direction::ALL.into_iter().zip(gs.move_previews()) {
+            assert_eq!(p.map(|p| p.merge_score), gs.score_if_moved(d));
+            assert_eq!(p.map_or(0, |p| p.merges), gs.merge_count(d));
+        }
+    }
+
+    #[test]
+    fn injected_rng_drives_spawns() {
+        use rand::SeedableRng;
+
+        let mut gs = GameState::with_rng(crate::GameRng::seed_from_u64(77));
+        let mut seeded = GameState::new_from_seed(77);
+        assert_eq!(gs, seeded);
+        assert_eq!(gs.seed(), None);
+        assert!(!gs.restart());
+        assert_eq!(gs.to_compact(), ".,.,.,./.,.,.,./2,.,.,./.,.,.,2");
+
+        for d in [Direction::Left, Direction::Up, Direction::Right] {
+            assert_eq!(gs.apply_spawning(d), seeded.apply_spawning(d));
+        }
+    }
+
+    #[test]
+    fn reloads_keep_the_spawn_sequence() {
+        let moves = [
+            Direction::Left,
+            Direction::Up,
+            Direction::Right,
+            Direction::Down,
+        ];
+        let mut gs = GameState::new_from_entropy();
+        for d in moves.repeat(3) {
+            gs.apply(d);
+        }
+        let mut reloaded: GameState =
+            serde_json::from_str(&serde_json::to_string(&gs).unwrap()).unwrap();
+        for d in moves.repeat(5) {
+            assert_eq!(reloaded.apply_spawning(d), gs.apply_spawning(d));
+            assert_eq!(reloaded, gs);
+        }
+
+        // saves from before the RNG was kept still load
+        let mut old = serde_json::to_value(&gs).unwrap();
+        old.as_object_mut().unwrap().remove("rng");
+        let old: GameState = serde_json::from_value(old).unwrap();
+        assert_eq!(old, gs);
+    }
+
+    #[test]
+    fn codes_pick_the_same_game() {
+        let a = GameState::new_from_code("sunny day");
+        assert_eq!(a, GameState::new_from_code("sunny day"));
+        assert_eq!(a, GameState::new_from_code("  Sunny Day "));
+        // pinned so a change to the hash, which would break shared codes, is caught
+        assert_eq!(a.seed(), Some(14350106544338784734));
+        assert_ne!(a.seed(), GameState::new_from_code("rainy day").seed());
+    }
+
+    #[test]
+    fn neighbor_cells() {
+        let neighbors = |i| GameState::neighbors(i).collect::<Vec<_>>();
+        // corners
+        assert_eq!(neighbors(0), [1, 4]);
+        assert_eq!(neighbors(3), [2, 7]);
+        assert_eq!(neighbors(12), [8, 13]);
+        assert_eq!(neighbors(15), [11, 14]);
+        // edges
+        assert_eq!(neighbors(1), [0, 2, 5]);
+        assert_eq!(neighbors(4), [0, 5, 8]);
+        assert_eq!(neighbors(7), [3, 6, 11]);
+        assert_eq!(neighbors(14), [10, 13, 15]);
+        // center
+        assert_eq!(neighbors(5), [1, 4, 6, 9]);
+        assert_eq!(neighbors(10), [6, 9, 11, 14]);
+
+        // rows wrap at the board's width, not at 4
+        assert_eq!(Board::<3, 2>::neighbors(2).collect::<Vec<_>>(), [1, 5]);
+        assert_eq!(Board::<3, 2>::neighbors(3).collect::<Vec<_>>(), [0, 4]);
+        assert!(std::panic::catch_unwind(|| GameState::neighbors(16).count()).is_err());
+    }
+
+    #[test]
+    fn adjacent_pairs() {
+        #[rustfmt::skip]
+        let gs = GameState::from_list([
+            2,  2,  8,  -1,
+            4,  16, 8,  -1,
+            4,  -1, -1, 32,
+            -1, -1, 32, 64,
+        ]);
+        // 2-2 across, 8-8 and 4-4 down; the 32s are diagonal and don't count
+        assert_eq!(gs.merge_potential(), 3);
+
+        // a gap between equal tiles isn't a pair, even though a move merges them
+        let gapped =
+            GameState::from_list([2, -1, 2, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1]);
+        assert_eq!(gapped.merge_potential(), 0);
+        assert_eq!(gapped.merge_count(Direction::Left), 1);
+    }
+
+    #[test]
+    fn difficulty_extremes() {
+        assert_eq!(GameState::from_list([-1; 16]).difficulty_score(), 0.);
+
+        let lost = GameState::from_list([2, 4, 2, 4, 4, 2, 4, 2, 2, 4, 2, 4, 4, 2, 4, 2]);
+        assert_eq!(lost.difficulty_score(), 1.);
+
+        #[rustfmt::skip]
+        let nearly_stuck = GameState::from_list([
+            2, 4, 2, 4,
+            4, 2, 4, 2,
+            2, 4, 2, 4,
+            4, 2, 4, -1,
+        ]);
+        let start =
+            GameState::from_list([2, -1, -1, -1, -1, -1, -1, -1, -1, -1, 2, -1, -1, -1, -1, -1]);
+        let hard = nearly_stuck.difficulty_score();
+        let easy = start.difficulty_score();
+        assert!(hard > 0.75 && hard < 1., "{hard}");
+        assert!(easy < 0.1, "{easy}");
+    }
+
+    /// every line of empties, 2s, 4s, 8s and 16s, against the rules written
+    /// out the obvious way: pack the tiles, merge equal neighbours from the
+    /// front, each tile merging at most once
+    #[test]
+    fn merge_line_matches_the_rules() {
+        let cell = |n: u32| Tile::from_exponent(n);
+        for code in 0..5u32.pow(4) {
+            let line: [Option<Tile>; 4] =
+                std::array::from_fn(|i| cell(code / 5u32.pow(i as u32) % 5));
+
+            let packed: Vec<Tile> = line.iter().flatten().copied().collect();
+            let mut expected = Vec::new();
+            let mut score = 0;
+            let mut i = 0;
+            while i < packed.len() {
+                if i + 1 < packed.len() && packed[i] == packed[i + 1] {
+                    let merged = packed[i].double();
+                    score += u64::from(merged.as_u32());
+                    expected.push(Some(merged));
+                    i += 2;
+                } else {
+                    expected.push(Some(packed[i]));
+                    i += 1;
+                }
+            }
+            expected.resize(4, None);
+
+            let (merged, gained) = merge_line(line);
+            assert_eq!(merged[..], expected[..], "{line:?}");
+            assert_eq!(gained, score, "{line:?}");
+        }
+    }
+
+    #[test]
+    fn move_strings() {
+        let mut gs = GameState::new_from_seed(14);
+        let mut by_hand = gs.clone();
+        let played = gs.apply_str("lurd, Left UP").unwrap();
+        let legal = [
+            Direction::Left,
+            Direction::Up,
+            Direction::Right,
+            Direction::Down,
+            Direction::Left,
+            Direction::Up,
+        ]
+        .into_iter()
+        .filter(|&d| by_hand.apply(d))
+        .count();
+        assert_eq!(played, legal);
+        assert_eq!(gs, by_hand);
+
+        // an up straight after an up can't do anything
+        let mut top = GameState::from_list_seeded(
+            [2, 4, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+            2,
+        );
+        assert_eq!(top.apply_str("uu").unwrap(), 0);
+
+        let before = gs.clone();
+        assert_eq!(gs.apply_str("lu x"), Err(InvalidMove("x".into())));
+        assert_eq!(
+            gs.apply_str("sideways"),
+            Err(InvalidMove("sideways".into()))
+        );
+        assert_eq!(gs.move_count(), before.move_count());
+    }
+
+    #[test]
+    fn every_tile_has_its_own_color() {
+        let tiles: Vec<_> = (1..32).map(|e| Tile::from_exponent(e).unwrap()).collect();
+        assert_ne!(tiles[19].background(), tiles[20].background());
+        for (i, a) in tiles.iter().enumerate() {
+            for b in &tiles[i + 1..] {
+                assert_ne!(a.background(), b.background(), "{a:?} and {b:?}");
+            }
+        }
+        assert!(tiles[13].has_palette_color());
+        assert!(!tiles[14].has_palette_color());
+        assert_eq!(Tile::TWO.background_hex(), "#14406b");
+    }
+
+    #[test]
+    fn exponent_grid() {
+        let gs = GameState::from_list([
+            2, -1, -1, 2048, 4, 8, -1, -1, -1, -1, -1, -1, 131072, -1, -1, 1024,
+        ]);
+        assert_eq!(
+            gs.debug_exponents(),
+            " 1  .  . 11\n 2  3  .  .\n .  .  .  .\n17  .  . 10"
+        );
+    }
+
+    #[test]
+    fn boards_from_exponents() {
+        let gs =
+            GameState::from_exponents([[1, 0, 0, 11], [2, 3, 0, 0], [0, 0, 0, 0], [17, 0, 0, 31]])
+                .unwrap();
+        assert_eq!(
+            gs.debug_exponents(),
+            " 1  .  . 11\n 2  3  .  .\n .  .  .  .\n17  .  . 31"
+        );
+        assert_eq!(gs.max(), 1 << 31);
+        let listed = GameState::from_list([
+            2, -1, -1, 2048, 4, 8, -1, -1, -1, -1, -1, -1, 131072, -1, -1, 1024,
+        ]);
+        assert_eq!(
+            GameState::from_exponents([[1, 0, 0, 11], [2, 3, 0, 0], [0; 4], [17, 0, 0, 10]]),
+            Some(listed)
+        );
+
+        assert_eq!(GameState::from_exponents([[0, 0, 0, 32]; 4]), None);
+        assert_eq!(
+            GameState::from_exponents([[0; 4], [0; 4], [0; 4], [0, 0, 0, 40]]),
+            None
+        );
+    }
+
+    #[test]
+    fn count_tiles() {
+        let gs =
+            GameState::from_list([4, 8, -1, -1, -1, 4, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1]);
+        assert_eq!(gs.count_tile(4), 2);
+        assert_eq!(gs.count_tile(8), 1);
+        assert_eq!(gs.count_tile(2), 0);
+        for not_a_tile in [0, 1, 6, 12] {
+            assert_eq!(gs.count_tile(not_a_tile), 0);
+        }
+    }
+
+    #[test]
+    fn forced_move() {
+        #[rustfmt::skip]
+        let forced = GameState::from_list([
+            2,  4,  8,  -1,
+            4,  8,  16, -1,
+            8,  16, 32, -1,
+            16, 32, 64, -1,
+        ]);
+        assert_eq!(forced.only_move(), Some(Direction::Right));
+
+        #[rustfmt::skip]
+        let two_ways = GameState::from_list([
+            2,  4,  8,  16,
+            4,  8,  16, 32,
+            8,  16, 32, 64,
+            16, 32, 64, -1,
+        ]);
+        assert_eq!(two_ways.only_move(), None);
+        assert_eq!(GameState::from_list([-1; 16]).only_move(), None);
+    }
+
+    #[test]
+    fn simulate_leaves_the_game_alone() {
+        let gs = GameState::new_from_seed(30);
+        let copy = gs.clone();
+        let next = gs.simulate(Direction::Left).unwrap();
+        assert_eq!(gs, copy);
+        assert_eq!(gs.move_count(), 0);
+        assert_ne!(next, gs);
+        assert_eq!(next.move_count(), 1);
+
+        // the same spawn as really playing the move
+        let mut played = gs.clone();
+        played.apply(Direction::Left);
+        assert_eq!(next, played);
+        assert_eq!(next.simulate(Direction::Up), played.simulate(Direction::Up));
+
+        let stuck = GameState::from_list_seeded(
+            [
+                2, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1,
+            ],
+            1,
+        );
+        assert!(stuck.simulate(Direction::Left).is_none());
+    }
+
+    #[test]
+    fn merge_breakdown_adds_up() {
+        let tile = |v| Tile::from(TileValue::try_from(v).unwrap());
+        #[rustfmt::skip]
+        let mut gs = GameState::from_list([
+            2,  2,  8,  8,
+            4,  -1, 4,  -1,
+            16, 16, 16, 16,
+            2,  4,  8,  16,
+        ]);
+        let before = gs.merge_score();
+        let merges = gs.do_move_detailed(Direction::Right);
+        assert_eq!(
+            merges,
+            [
+                Merge {
+                    index: 3,
+                    tile: tile(16)
+                },
+                Merge {
+                    index: 2,
+                    tile: tile(4)
+                },
+                Merge {
+                    index: 7,
+                    tile: tile(8)
+                },
+                Merge {
+                    index: 11,
+                    tile: tile(32)
+                },
+                Merge {
+                    index: 10,
+                    tile: tile(32)
+                },
+            ]
+        );
+        assert_eq!(
+            merges.iter().map(Merge::points).sum::<u64>(),
+            gs.merge_score() - before
+        );
+
+        // the same as a plain move, down to the score
+        let mut replay = GameState::new_from_seed(8);
+        let mut plain = replay.clone();
+        for d in [
+            Direction::Left,
+            Direction::Up,
+            Direction::Right,
+            Direction::Down,
+        ]
+        .repeat(6)
+        {
+            let before = replay.merge_score();
+            let points: u64 = replay.do_move_detailed(d).iter().map(Merge::points).sum();
+            plain.do_move(d);
+            assert_eq!(replay, plain);
+            assert_eq!(points, replay.merge_score() - before);
+            assert_eq!(replay.merge_score(), plain.merge_score());
+        }
+    }
+
+    #[test]
+    fn zen_clears_the_smallest_tiles() {
+        #[rustfmt::skip]
+        let mut stuck = GameState::from_list([
+            4,  8,  16, 32,
+            8,  16, 32, 64,
+            16, 32, 64, 128,
+            32, 64, 128, 256,
+        ]);
+        assert!(stuck.lost());
+        assert_eq!(stuck.unstick(), [0]);
+        assert_eq!(stuck.rows()[0][0], None);
+        assert!(!stuck.lost());
+        // only stuck boards are touched
+        assert!(stuck.unstick().is_empty());
+        assert_eq!(stuck.empty_count(), 1);
+
+        // a move that fills the last cell is unstuck straight away
+        #[rustfmt::skip]
+        let mut zen = GameState::from_list_seeded([
+            8,  16, 32,  64,
+            16, 32, 64,  128,
+            32, 64, 128, 256,
+            -1, 128, 256, 512,
+        ], 2)
+        .with_rules(Rules {
+            zen: true,
+            ..Rules::default()
+        });
+        let mut plain = zen.clone().with_rules(Rules::default());
+        assert!(plain.apply(Direction::Left));
+        assert!(plain.lost());
+        assert!(zen.apply(Direction::Left));
+        assert!(!zen.lost());
+        let smallest = plain.cells().iter().flatten().min().unwrap().as_u32();
+        assert_eq!(zen.count_tile(smallest), 0);
+        assert_eq!(zen.empty_count(), plain.count_tile(smallest));
+
+        // and undo puts back what was cleared along with the move
+        assert!(zen.undo());
+        assert_eq!(zen.empty_count(), 1);
+    }
+
+    #[test]
+    fn unreachable_targets() {
+        let limited = |moves| Rules {
+            move_limit: Some(moves),
+            ..Rules::default()
+        };
+        // 36 on the board and at most 4 from the one move left
+        let poor =
+            GameState::from_list([2, 2, 32, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1])
+                .with_rules(limited(1));
+        assert!(!poor.target_reachable(64));
+        assert!(poor.target_reachable(40));
+        assert!(poor.target_reachable(32));
+
+        let rich = GameState::from_list([
+            512, 512, 512, 512, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1,
+        ])
+        .with_rules(limited(1));
+        assert!(rich.target_reachable(2048));
+
+        #[rustfmt::skip]
+        let lost = GameState::from_list([
+            2,  4,  8,  16,
+            4,  8,  16, 32,
+            8,  16, 32, 64,
+            16, 32, 64, 128,
+        ]);
+        assert!(!lost.target_reachable(256));
+        assert!(lost.target_reachable(128));
+
+        // spawns keep coming while there's no limit
+        assert!(GameState::new_from_seed(3).target_reachable(2048));
+    }
+
+    #[test]
+    fn near_loss_boundaries() {
+        #[rustfmt::skip]
+        let one_pair = GameState::from_list([
+            2,  2,  8,  16,
+            4,  8,  16, 32,
+            8,  16, 32, 64,
+            16, 32, 64, 128,
+        ]);
+        assert!(one_pair.is_near_loss());
+
+        #[rustfmt::skip]
+        let two_pairs = GameState::from_list([
+            2,  2,  8,   16,
+            4,  8,  16,  32,
+            8,  16, 32,  64,
+            16, 32, 128, 128,
+        ]);
+        assert!(!two_pairs.is_near_loss());
+
+        #[rustfmt::skip]
+        let one_empty = GameState::from_list([
+            2,  4,  8,  16,
+            4,  8,  16, 32,
+            8,  16, 32, 64,
+            16, 32, 64, -1,
+        ]);
+        assert!(one_empty.is_near_loss());
+
+        #[rustfmt::skip]
+        let two_empty = GameState::from_list([
+            2,  4,  8,  16,
+            4,  8,  16, 32,
+            8,  16, 32, 64,
+            16, 32, -1, -1,
+        ]);
+        assert!(!two_empty.is_near_loss());
+
+        #[rustfmt::skip]
+        let lost = GameState::from_list([
+            2,  4,  8,  16,
+            4,  8,  16, 32,
+            8,  16, 32, 64,
+            16, 32, 64, 128,
+        ]);
+        assert!(lost.lost());
+        assert!(!lost.is_near_loss());
+    }
+
+    #[test]
+    fn all_directions() {
+        let all: Vec<Direction> = Direction::all().collect();
+        assert_eq!(all, Direction::ALL);
+        assert_eq!(all.len(), 4);
+        for d in [
+            Direction::Up,
+            Direction::Down,
+            Direction::Left,
+            Direction::Right,
+        ] {
+            assert_eq!(all.iter().filter(|&&a| a == d).count(), 1);
+        }
+    }
+
+    #[test]
+    fn merge_counts() {
+        let gs = GameState::from_list([2, 2, 2, 2, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1]);
+        assert_eq!(gs.merge_count(Direction::Left), 2);
+        assert_eq!(gs.merge_count(Direction::Right), 2);
+        assert_eq!(gs.merge_count(Direction::Down), 0);
+        assert_eq!(gs.merge_count(Direction::Up), 0);
+    }
+
+    #[test]
+    fn best_merge() {
+        // 256s merge vertically, the 2s horizontally
+        let gs = GameState::from_list([
+            256, 2, 2, -1, 256, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1,
+        ]);
+        assert_eq!(gs.score_if_moved(Direction::Left), Some(4));
+        assert_eq!(gs.best_merge_cell(), Some((Direction::Up, 512)));
+        assert_eq!(gs.merge_score(), 0);
+
+        assert_eq!(
+            GameState::from_list([2, 4, 2, 4, 4, 2, 4, 2, 2, 4, 2, 4, 4, 2, 4, 2,])
+                .best_merge_cell(),
+            None
+        );
     }
 }
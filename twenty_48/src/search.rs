@@ -0,0 +1,137 @@
+use std::time::{Duration, Instant};
+
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::{heuristics::evaluate, Direction, GameState};
+
+/// what a position with no legal moves is worth, well below anything `evaluate` gives
+const LOST: f64 = -1e9;
+
+/// Expectimax to `depth` moves: our moves maximize, spawns are averaged
+/// over `possible_spawns`. `None` if there's no legal move.
+pub fn best_move(gs: &GameState, depth: u32) -> Option<Direction> {
+    search(gs, depth.max(1), None)
+}
+
+/// Iterative-deepening `best_move` that stops once `budget` has passed and
+/// returns the move from the deepest search that finished. Depth 1 always
+/// runs to completion, so any live position gets a move however small the
+/// budget.
+pub fn best_move_timed(gs: &GameState, budget: Duration) -> Option<Direction> {
+    let deadline = Instant::now() + budget;
+    let mut best = search(gs, 1, None)?;
+    for depth in 2.. {
+        if Instant::now() >= deadline {
+            break;
+        }
+        match search(gs, depth, Some(deadline)) {
+            Some(d) => best = d,
+            None => break, // ran out of time partway through
+        }
+    }
+    Some(best)
+}
+
+/// A deliberately weakened player for adjustable difficulty: with
+/// probability `strength` it plays `best_move`, otherwise a random legal
+/// move. Its choices come from its own seeded RNG, so a given seed and
+/// sequence of positions always gets the same moves.
+pub struct Throttled {
+    strength: f64,
+    depth: u32,
+    rng: StdRng,
+}
+
+impl Throttled {
+    /// `strength` is clamped to `0.0..=1.0`; searches 2 moves deep
+    pub fn new(strength: f64, seed: u64) -> Self {
+        Self {
+            strength: strength.clamp(0., 1.),
+            depth: 2,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn next_move(&mut self, gs: &GameState) -> Option<Direction> {
+        if self.rng.gen_bool(self.strength) {
+            best_move(gs, self.depth)
+        } else {
+            gs.valid_moves().choose(&mut self.rng).copied()
+        }
+    }
+}
+
+/// `None` if there's no move or `deadline` passed before the search finished
+fn search(gs: &GameState, depth: u32, deadline: Option<Instant>) -> Option<Direction> {
+    let mut best = None;
+    for d in gs.valid_moves() {
+        let value = chance(&gs.preview_move(d).unwrap(), depth - 1, deadline)?;
+        if best.is_none_or(|(_, v)| value > v) {
+            best = Some((d, value));
+        }
+    }
+    best.map(|(d, _)| d)
+}
+
+fn max(gs: &GameState, depth: u32, deadline: Option<Instant>) -> Option<f64> {
+    if deadline.is_some_and(|t| Instant::now() >= t) {
+        return None;
+    }
+    if depth == 0 {
+        return Some(evaluate(gs));
+    }
+    let mut best = LOST;
+    for d in gs.valid_moves() {
+        best = best.max(chance(&gs.preview_move(d).unwrap(), depth - 1, deadline)?);
+    }
+    Some(best)
+}
+
+/// expected value over every spawn that could follow a move
+fn chance(gs: &GameState, depth: u32, deadline: Option<Instant>) -> Option<f64> {
+    let mut total = 0.;
+    for (i, tile, p) in gs.possible_spawns() {
+        let mut next = gs.clone();
+        next.spawn_at(i, tile);
+        total += p * max(&next, depth, deadline)?;
+    }
+    Some(total)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::Throttled;
+    use crate::GameState;
+
+    #[test]
+    fn tiny_budget_still_moves() {
+        let gs = GameState::new_from_seed(3);
+        let d = super::best_move_timed(&gs, Duration::ZERO).unwrap();
+        assert!(gs.can_move(d));
+        assert_eq!(Some(d), super::best_move(&gs, 1));
+
+        let d = super::best_move_timed(&gs, Duration::from_millis(20)).unwrap();
+        assert!(gs.can_move(d));
+
+        let lost = GameState::from_list([2, 4, 2, 4, 4, 2, 4, 2, 2, 4, 2, 4, 4, 2, 4, 2]);
+        assert_eq!(
+            super::best_move_timed(&lost, Duration::from_millis(20)),
+            None
+        );
+    }
+
+    #[test]
+    fn full_strength_plays_best_move() {
+        let mut gs = GameState::new_from_seed(8);
+        let mut strongest = Throttled::new(1.0, 0);
+        let mut weakest = Throttled::new(0.0, 0);
+        for _ in 0..10 {
+            let best = super::best_move(&gs, 2);
+            assert_eq!(strongest.next_move(&gs), best);
+            assert!(gs.can_move(weakest.next_move(&gs).unwrap()));
+            gs.apply(best.unwrap());
+        }
+    }
+}
@@ -1,55 +1,265 @@
-use crate::{Direction, GameState};
+use std::collections::BTreeMap;
 
-pub fn solver_up_right_left_down(gs: &mut GameState) {
-    while !gs.lost() {
-        for d in [
+use crate::{
+    heuristics::{corner_gradient, Corner, HeuristicWeights},
+    Direction, GameState, GameSummary,
+};
+
+/// Picks the next move for a position, `None` to give up
+pub type Solver = fn(&GameState) -> Option<Direction>;
+
+/// Every built-in solver, keyed by the name the CLI knows it as
+pub fn registry() -> BTreeMap<&'static str, Solver> {
+    BTreeMap::from([
+        ("random", solver_random as Solver),
+        ("maxempty", solver_max_empty),
+        ("urld", solver_up_right_left_down),
+        ("snake", solver_snake),
+        ("snake-adaptive", solver_snake_adaptive),
+        ("weighted", |gs| {
+            solver_weighted(gs, &HeuristicWeights::default())
+        }),
+    ])
+}
+
+/// Let `solver` play `gs` until the game is finished or the solver gives up
+pub fn play(gs: &mut GameState, solver: Solver) -> GameSummary {
+    gs.apply_all_then_report(solver)
+}
+
+/// `play`, calling `on_move` with the board after every move
+pub fn play_observed(
+    gs: &mut GameState,
+    solver: Solver,
+    mut on_move: impl FnMut(&GameState),
+) -> GameSummary {
+    // the board after a move is next seen when the solver is asked about it,
+    // except for the last one, which finishes the game
+    let mut seen = gs.move_count();
+    let summary = gs.apply_all_then_report(|g| {
+        if g.move_count() != seen {
+            seen = g.move_count();
+            on_move(g);
+        }
+        solver(g)
+    });
+    if gs.move_count() != seen {
+        on_move(gs);
+    }
+    summary
+}
+
+/// the first of `dirs` that's legal
+fn first_legal(gs: &GameState, dirs: [Direction; 4]) -> Option<Direction> {
+    dirs.into_iter().find(|&d| gs.can_move(d))
+}
+
+/// A uniformly random legal move from `GameState::pick_random_move`, the
+/// baseline every real solver should beat
+pub fn solver_random(gs: &GameState) -> Option<Direction> {
+    gs.pick_random_move()
+}
+
+pub fn solver_up_right_left_down(gs: &GameState) -> Option<Direction> {
+    first_legal(
+        gs,
+        [
+            Direction::Up,
+            Direction::Right,
+            Direction::Left,
+            Direction::Down,
+        ],
+    )
+}
+
+pub fn solver_snake(gs: &GameState) -> Option<Direction> {
+    let priority = if gs.can_move_row(0) {
+        [
+            Direction::Up,
+            Direction::Left,
+            Direction::Right,
+            Direction::Down,
+        ]
+    } else if gs.can_move_row(1) {
+        [
             Direction::Up,
             Direction::Right,
             Direction::Left,
             Direction::Down,
-        ] {
-            if gs.can_move(d) {
-                gs.do_move(d);
-                gs.spawn_tile();
-                break;
-            }
+        ]
+    } else {
+        [
+            Direction::Up,
+            Direction::Left,
+            Direction::Right,
+            Direction::Down,
+        ]
+    };
+    first_legal(gs, priority)
+}
+
+/// Like `solver_snake`, but instead of a fixed orientation it keeps the max
+/// tile locked in whichever corner it's nearest. Moves toward that corner
+/// are always preferred, picking between them (and then between the rest)
+/// by `corner_gradient` plus a bonus for empty cells.
+pub fn solver_snake_adaptive(gs: &GameState) -> Option<Direction> {
+    let corner = Corner::of_max_tile(gs)?;
+    let toward = corner.toward();
+    let score = |d: Direction| {
+        gs.preview_move(d)
+            .map(|p| corner_gradient(&p, corner) + 16 * p.empty_count() as i64)
+    };
+
+    let best = |dirs: &[Direction]| {
+        dirs.iter()
+            .filter_map(|&d| score(d).map(|s| (d, s)))
+            .max_by_key(|(_, s)| *s)
+            .map(|(d, _)| d)
+    };
+    best(&toward).or_else(|| {
+        let away: Vec<Direction> = Direction::ALL
+            .into_iter()
+            .filter(|d| !toward.iter().any(|t| t == d))
+            .collect();
+        best(&away)
+    })
+}
+
+/// Keep the board open: the legal move leaving the most empty cells before
+/// the spawn, breaking ties by merge score
+pub fn solver_max_empty(gs: &GameState) -> Option<Direction> {
+    gs.valid_moves()
+        .into_iter()
+        .map(|d| {
+            let p = gs.preview_move(d).unwrap();
+            (d, (p.empty_count(), p.merge_score()))
+        })
+        .max_by_key(|(_, key)| *key)
+        .map(|(d, _)| d)
+}
+
+/// Greedy one move lookahead: the legal move whose result `weights` scores
+/// highest. Not a `Solver` itself since it needs the weights; wrap it in a
+/// closure to play with it.
+pub fn solver_weighted(gs: &GameState, weights: &HeuristicWeights) -> Option<Direction> {
+    gs.valid_moves()
+        .into_iter()
+        .map(|d| (d, weights.score(&gs.preview_move(d).unwrap())))
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(d, _)| d)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::GameState;
+
+    #[test]
+    fn registry_resolves() {
+        let registry = super::registry();
+        assert!(!registry.is_empty());
+        for name in registry.keys() {
+            let solver = registry[name];
+            let mut gs = GameState::new_from_seed(1);
+            super::play(&mut gs, solver);
+            assert!(gs.finished(), "{name} stopped early");
         }
     }
-}
 
-pub fn solver_snake(gs: &mut GameState) {
-    while !gs.lost() {
-        // println!("{gs}");
-        // stdin().read(&mut [0; 1024]).unwrap();
-        let priority = if gs.can_move_row(0) {
-            [
-                Direction::Up,
-                Direction::Left,
-                Direction::Right,
-                Direction::Down,
-            ]
-        } else if gs.can_move_row(1) {
-            [
-                Direction::Up,
-                Direction::Right,
-                Direction::Left,
-                Direction::Down,
-            ]
-        } else {
-            [
-                Direction::Up,
-                Direction::Left,
-                Direction::Right,
-                Direction::Down,
-            ]
+    #[test]
+    fn random_plays_legal_moves_until_lost() {
+        let mut gs = GameState::new_from_seed(3);
+        let mut seen = [false; 4];
+        while let Some(d) = super::solver_random(&gs) {
+            assert!(gs.apply(d), "{d:?} isn't legal");
+            seen[d as usize] = true;
+        }
+        assert!(gs.lost());
+        assert_eq!(seen, [true; 4]);
+    }
+
+    #[test]
+    fn max_empty_opens_the_board() {
+        use crate::Direction::*;
+
+        // two small merges sideways beat one big one vertically
+        #[rustfmt::skip]
+        let gs = GameState::from_list([
+            2, 2,  4, 4,
+            -1, 32, -1, -1,
+            -1, 32, -1, -1,
+            -1, -1, -1, -1,
+        ]);
+        assert!(matches!(super::solver_max_empty(&gs), Some(Left | Right)));
+
+        // one merge either way, so the bigger one wins
+        #[rustfmt::skip]
+        let gs = GameState::from_list([
+            2,  2,  -1, -1,
+            -1, -1, -1, 32,
+            -1, -1, -1, 32,
+            -1, -1, -1, -1,
+        ]);
+        assert!(matches!(super::solver_max_empty(&gs), Some(Up | Down)));
+    }
+
+    // 200 full games; slow in debug builds, run with `--ignored`
+    #[test]
+    #[ignore]
+    fn adaptive_snake_beats_snake() {
+        let mean_max = |solver| {
+            let results = crate::bench::run(solver, 100, 0);
+            results.iter().map(|r| u64::from(r.max_tile)).sum::<u64>() / 100
         };
+        let snake = mean_max(super::solver_snake);
+        let adaptive = mean_max(super::solver_snake_adaptive);
+        assert!(adaptive > snake, "adaptive {adaptive} vs snake {snake}");
+    }
 
-        for d in priority {
-            if gs.can_move(d) {
-                gs.do_move(d);
-                gs.spawn_tile_with_dir(d);
-                break;
-            }
+    /// Mean max tile over seeds 0..200 for each solver, each floor set a
+    /// bit below what it scored when this was written (random 103, urld 196,
+    /// snake 185, snake-adaptive 245, weighted 236, maxempty 231). A solver dropping
+    /// under its floor is a regression; one that got better can have its
+    /// floor raised. Slow in debug builds, so run it with
+    /// `cargo test --release -- --ignored`.
+    #[test]
+    #[ignore]
+    fn solvers_clear_their_floors() {
+        let floors = [
+            ("random", 90),
+            ("urld", 170),
+            ("snake", 160),
+            ("snake-adaptive", 215),
+            ("weighted", 205),
+            ("maxempty", 200),
+        ];
+        let registry = super::registry();
+        let mut means = std::collections::BTreeMap::new();
+        for (name, floor) in floors {
+            let results = crate::bench::run(registry[name], 200, 0);
+            let mean = results.iter().map(|r| u64::from(r.max_tile)).sum::<u64>() / 200;
+            assert!(
+                mean >= floor,
+                "{name} averaged {mean}, under its floor of {floor}"
+            );
+            means.insert(name, mean);
         }
+        assert_eq!(means.len(), registry.len(), "every solver needs a floor");
+        assert!(means
+            .iter()
+            .all(|(&n, &m)| n == "random" || m > means["random"]));
+        assert!(means["snake-adaptive"] > means["snake"]);
+    }
+
+    #[test]
+    fn observer_sees_every_move() {
+        let mut gs = GameState::new_from_seed(2);
+        let mut moves = 0;
+        super::play_observed(&mut gs, super::solver_snake, |g| {
+            moves += 1;
+            assert_eq!(g.move_count(), moves);
+        });
+        assert!(moves > 0);
+        assert_eq!(gs.move_count(), moves);
+        assert!(gs.finished());
     }
 }
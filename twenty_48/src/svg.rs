@@ -0,0 +1,90 @@
+use std::fmt::Write;
+
+use crate::Board;
+
+const CELL: usize = 100;
+const GAP: usize = 10;
+
+impl<const W: usize, const H: usize> Board<W, H> {
+    /// The board as a self-contained SVG, colored like the web version, for
+    /// pasting into issues or docs
+    pub fn to_svg(&self) -> String {
+        let pos = |i: usize| GAP + i * (CELL + GAP);
+        let (width, height) = (pos(W), pos(H));
+
+        let mut svg = format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}">"#
+        );
+        write!(
+            svg,
+            r##"<rect width="{width}" height="{height}" rx="{GAP}" fill="#4d4d4d"/>"##
+        )
+        .unwrap();
+        for (r, row) in self.rows().iter().enumerate() {
+            for (c, t) in row.iter().enumerate() {
+                let (x, y) = (pos(c), pos(r));
+                let fill = t.map_or("#2f2f2f".into(), |t| t.background_hex());
+                write!(
+                    svg,
+                    r#"<rect x="{x}" y="{y}" width="{CELL}" height="{CELL}" rx="15" fill="{fill}"/>"#
+                )
+                .unwrap();
+                if let Some(t) = t {
+                    let value = t.as_u32();
+                    // shrink long numbers so they stay inside the cell
+                    let size = (CELL * 2 / 5).min(CELL * 6 / 5 / value.to_string().len());
+                    write!(
+                        svg,
+                        r#"<text x="{}" y="{}" font-size="{size}" font-family="serif" font-weight="bold" fill="white" text-anchor="middle" dominant-baseline="central">{value}</text>"#,
+                        x + CELL / 2,
+                        y + CELL / 2,
+                    )
+                    .unwrap();
+                }
+            }
+        }
+        svg.push_str("</svg>");
+        svg
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::GameState;
+
+    /// every tag is closed, and closed in the order it was opened
+    fn balanced(xml: &str) -> bool {
+        let mut open = Vec::new();
+        for tag in xml.split('<').skip(1) {
+            let Some((tag, _)) = tag.split_once('>') else {
+                return false;
+            };
+            if let Some(name) = tag.strip_prefix('/') {
+                if open.pop() != Some(name) {
+                    return false;
+                }
+            } else if !tag.ends_with('/') {
+                open.push(tag.split(' ').next().unwrap());
+            }
+        }
+        open.is_empty()
+    }
+
+    #[test]
+    fn svg_shows_tiles() {
+        let gs = GameState::from_list([
+            2, 4, -1, -1, -1, 2048, -1, -1, -1, -1, -1, -1, -1, -1, -1, 131072,
+        ]);
+        let svg = gs.to_svg();
+        assert!(svg.starts_with("<svg "));
+        assert!(balanced(&svg), "{svg}");
+        for value in [2, 4, 2048, 131072] {
+            assert!(svg.contains(&format!(">{value}</text>")), "{value}");
+        }
+        assert_eq!(svg.matches("<text").count(), 4);
+        assert_eq!(svg.matches("<rect").count(), 17);
+        assert!(svg.contains(r##"fill="#14406b""##));
+
+        assert!(!balanced("<svg><rect></svg>"));
+    }
+}
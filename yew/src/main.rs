@@ -1,8 +1,23 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+};
+
 use num_format::{Locale, ToFormattedString};
-use serde::de::DeserializeOwned;
-use twenty_48::{Direction, GameState};
-use web_sys::{js_sys::Date, window, HtmlDialogElement, HtmlElement};
-use yew::prelude::*;
+use serde::{de::DeserializeOwned, Serialize};
+use twenty_48::{
+    achievements::{Achievements, MILESTONE_TILES},
+    game_log::GameLog,
+    heuristics::Corner,
+    Direction, GameState, Rules, UndoPolicy,
+};
+use wasm_bindgen::{closure::Closure, JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+    js_sys::Date, window, HtmlDialogElement, HtmlElement, HtmlInputElement, HtmlTextAreaElement,
+    Response,
+};
+use yew::{html::Scope, prelude::*};
 
 enum Action {
     Move(Direction),
@@ -10,9 +25,36 @@ enum Action {
     TouchEnd,
     TouchMove(TouchEvent),
     NewGame,
+    /// start the game the code box names
+    PlayCode,
+    Restart,
     Undo,
     OpenScoreboard,
     CloseScoreboard,
+    ToggleHelp,
+    CloseHelp,
+    /// load a theme from the theme box, as JSON or a URL to fetch it from
+    LoadTheme,
+    ThemeFetched(Result<String, String>),
+    ResetTheme,
+    ExportStats,
+    ImportStats,
+    CycleBoardSize,
+    CycleUndoPolicy,
+    CycleSpawnsPerMove,
+    ToggleZen,
+    ToggleSpawnAnimation,
+    /// show (or with `None`, stop showing) where tiles would go for a move
+    Ghost(Option<Direction>),
+    ToggleGhosts,
+    ToggleCountUndos,
+    TogglePinCorner,
+    Resize,
+    /// show the replay after this many moves
+    Scrub(usize),
+    /// redraw the timer
+    Tick,
+    VisibilityChanged,
 }
 
 impl From<Direction> for Action {
@@ -21,34 +63,444 @@ impl From<Direction> for Action {
     }
 }
 
-#[derive(Default)]
-struct Scoreboard([Option<(u64, String)>; 5]);
+#[derive(Default, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+/// (score, date, assisted)
+struct Scoreboard([Option<(u64, String, bool)>; 5]);
 
-#[derive(Default, serde::Serialize, serde::Deserialize)]
+#[derive(Default, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
 struct StatsHistory(Vec<PastGameDatapoint>);
 
-#[derive(Default)]
+#[derive(Default, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
 struct Stats {
     history: StatsHistory,
 
     lifetime_points: u64,
 
+    /// rebuilt from the history rather than read back, see `Stats::import`
+    #[serde(skip_deserializing)]
     scoreboard: Scoreboard,
+
+    #[serde(default)]
+    achievements: Achievements,
 }
 
-#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize)]
 struct PastGameDatapoint {
     date: String,
     score: u64,
+    /// `GameState::fingerprint` of the finished game, 0 for games saved before it was recorded
+    #[serde(default)]
+    id: u64,
+    /// highest tile the game ever had, which undo can take off the final
+    /// board; 0 for games saved before it was recorded
+    #[serde(default)]
+    best_tile: u32,
+    /// undos taken, when undos were being counted; 0 for games saved before
+    /// they were
+    #[serde(default)]
+    undos_used: u32,
+}
+
+impl PastGameDatapoint {
+    /// finished with the help of undo, marked with an asterisk on the scoreboard
+    fn assisted(&self) -> bool {
+        self.undos_used > 0
+    }
+}
+
+/// Board colors, pasted or fetched as JSON like
+/// `{"background": "#4d4d4d", "text": "#fff", "tiles": {"1": "#14406b", "11": "#d4af37"}}`.
+/// Tiles are keyed by exponent, and any left out keep the stylesheet's color.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Theme {
+    background: String,
+    text: String,
+    tiles: BTreeMap<u32, String>,
+}
+
+impl Theme {
+    /// Parse and check a theme. Colors must be `#rgb` or `#rrggbb`, which
+    /// also keeps anything but a color out of the page's style.
+    fn parse(json: &str) -> Result<Theme, String> {
+        let theme: Theme = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        theme.validate()?;
+        Ok(theme)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let check = |what: &str, color: &str| {
+            let hex = color.strip_prefix('#').unwrap_or("");
+            if matches!(hex.len(), 3 | 6) && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                Ok(())
+            } else {
+                Err(format!("{what} color {color:?} isn't #rgb or #rrggbb"))
+            }
+        };
+        check("background", &self.background)?;
+        check("text", &self.text)?;
+        for (&exponent, color) in &self.tiles {
+            if twenty_48::Tile::from_exponent(exponent).is_none() {
+                return Err(format!("no tile has exponent {exponent}"));
+            }
+            check(&format!("tile {exponent}"), color)?;
+        }
+        Ok(())
+    }
+
+    /// the variables `index.scss` reads its colors from
+    fn css(&self) -> String {
+        let mut css = format!(
+            "--theme-background: {}; --theme-text: {};",
+            self.background, self.text
+        );
+        for (exponent, color) in &self.tiles {
+            css.push_str(&format!(" --tile-{exponent}: {color};"));
+        }
+        css
+    }
+}
+
+/// Set the theme's variables on the whole page, or with `None` go back to
+/// the stylesheet's colors
+fn apply_theme(theme: Option<&Theme>) {
+    let root = window()
+        .unwrap()
+        .document()
+        .unwrap()
+        .document_element()
+        .unwrap();
+    match theme {
+        Some(theme) => root.set_attribute("style", &theme.css()).unwrap(),
+        None => root.remove_attribute("style").unwrap(),
+    }
+}
+
+async fn fetch_text(url: String) -> Result<String, String> {
+    let js_err = |e: JsValue| format!("{e:?}");
+    let response: Response = JsFuture::from(window().unwrap().fetch_with_str(&url))
+        .await
+        .map_err(js_err)?
+        .dyn_into()
+        .map_err(js_err)?;
+    if !response.ok() {
+        return Err(format!("{url} answered {}", response.status()));
+    }
+    JsFuture::from(response.text().map_err(js_err)?)
+        .await
+        .map_err(js_err)?
+        .as_string()
+        .ok_or_else(|| format!("{url} didn't send text"))
+}
+
+/// User preference for how much of the viewport the board takes up
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+enum BoardSize {
+    Small,
+    Medium,
+    Large,
+}
+
+impl BoardSize {
+    fn scale(self) -> f64 {
+        match self {
+            BoardSize::Small => 0.7,
+            BoardSize::Medium => 0.85,
+            BoardSize::Large => 1.0,
+        }
+    }
+
+    fn next(self) -> BoardSize {
+        match self {
+            BoardSize::Small => BoardSize::Medium,
+            BoardSize::Medium => BoardSize::Large,
+            BoardSize::Large => BoardSize::Small,
+        }
+    }
+}
+
+/// Tile dimensions in pixels, handed to the stylesheet as CSS custom properties
+#[derive(Debug, PartialEq)]
+struct Layout {
+    tile: f64,
+    gap: f64,
+}
+
+impl Layout {
+    fn new((width, height): (f64, f64), size: BoardSize) -> Layout {
+        // leave room below the board for the score and buttons
+        let side = width.min(height * 0.8) * size.scale();
+        let cell = side / 4.;
+        let gap = cell * 0.05;
+        Layout {
+            tile: cell - 2. * gap,
+            gap,
+        }
+    }
+
+    fn css(&self) -> String {
+        format!(
+            "--tile-size: {:.1}px; --grid-gap: {:.1}px;",
+            self.tile, self.gap
+        )
+    }
+
+    /// (distance along, max drift across) a swipe needs to count as a move
+    fn swipe_thresholds(&self) -> (i32, i32) {
+        ((self.tile * 0.6) as i32, (self.tile * 0.3) as i32)
+    }
+}
+
+/// Bumped whenever the saved game's format changes; `migrate_save` knows how
+/// to upgrade every earlier version.
+const SAVE_VERSION: u64 = 1;
+
+/// Turn whatever is stored under `Model::LS_KEY_GAME` into a playable game.
+/// Version 0 is a bare `GameState`, from before saves were versioned.
+fn migrate_save(json: &str) -> Result<GameState, String> {
+    let mut save: serde_json::Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+    let version = save.get("version").and_then(serde_json::Value::as_u64);
+    let game = match version {
+        None => save,
+        Some(SAVE_VERSION) => save["game"].take(),
+        Some(v) => return Err(format!("unknown save version {v}")),
+    };
+    let gs: GameState = serde_json::from_value(game).map_err(|e| e.to_string())?;
+    if gs.is_valid() {
+        Ok(gs)
+    } else {
+        Err("saved game is not a reachable board".into())
+    }
+}
+
+/// Time spent on the current game, counting only while the tab is visible.
+/// Pausing and resuming are idempotent, so a burst of visibility events can't
+/// count a stretch twice.
+#[derive(Debug, Default, PartialEq)]
+struct GameTimer {
+    /// milliseconds from stretches that have ended
+    active_ms: f64,
+    /// `Date::now()` when the current stretch started, if there is one
+    since: Option<f64>,
+}
+
+impl GameTimer {
+    fn new(active_ms: f64) -> Self {
+        Self {
+            active_ms,
+            since: None,
+        }
+    }
+
+    fn resume(&mut self, now: f64) {
+        self.since.get_or_insert(now);
+    }
+
+    fn pause(&mut self, now: f64) {
+        if let Some(since) = self.since.take() {
+            // the clock can jump backwards, which shouldn't take time away
+            self.active_ms += (now - since).max(0.);
+        }
+    }
+
+    fn running(&self) -> bool {
+        self.since.is_some()
+    }
+
+    fn elapsed_ms(&self, now: f64) -> f64 {
+        self.active_ms + self.since.map_or(0., |since| (now - since).max(0.))
+    }
+}
+
+/// like `1:05`, or `1:02:05` past an hour
+fn format_duration(ms: f64) -> String {
+    let secs = (ms / 1000.) as u64;
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, m, s) => format!("{m}:{s:02}"),
+        (h, m, s) => format!("{h}:{m:02}:{s:02}"),
+    }
+}
+
+fn tab_hidden() -> bool {
+    window().unwrap().document().unwrap().hidden()
+}
+
+/// How the board is mirrored on screen to keep the biggest tile in one
+/// corner. Only the picture changes: directions from the keyboard or swipes
+/// are mapped back through `to_board` before they reach the game.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct View {
+    flip_rows: bool,
+    flip_cols: bool,
+}
+
+impl View {
+    /// shows `gs`'s biggest tile in `corner`
+    fn pinning(gs: &GameState, corner: Corner) -> View {
+        let top = |c: Corner| c.toward().contains(&Direction::Up);
+        let left = |c: Corner| c.toward().contains(&Direction::Left);
+        match Corner::of_max_tile(gs) {
+            Some(max) => View {
+                flip_rows: top(max) != top(corner),
+                flip_cols: left(max) != left(corner),
+            },
+            None => View::default(),
+        }
+    }
+
+    /// The board cell shown at (row, col) on screen. Flips undo
+    /// themselves, so this also maps board cells to the screen.
+    fn cell(self, row: usize, col: usize) -> (usize, usize) {
+        (
+            if self.flip_rows { 3 - row } else { row },
+            if self.flip_cols { 3 - col } else { col },
+        )
+    }
+
+    fn show<T: Copy>(self, rows: [[T; 4]; 4]) -> [[T; 4]; 4] {
+        std::array::from_fn(|r| {
+            std::array::from_fn(|c| {
+                let (br, bc) = self.cell(r, c);
+                rows[br][bc]
+            })
+        })
+    }
+
+    /// the real direction for a direction on screen
+    fn to_board(self, dir: Direction) -> Direction {
+        match dir {
+            Direction::Up | Direction::Down if self.flip_rows => opposite(dir),
+            Direction::Left | Direction::Right if self.flip_cols => opposite(dir),
+            _ => dir,
+        }
+    }
+}
+
+fn opposite(dir: Direction) -> Direction {
+    match dir {
+        Direction::Up => Direction::Down,
+        Direction::Down => Direction::Up,
+        Direction::Left => Direction::Right,
+        Direction::Right => Direction::Left,
+    }
+}
+
+/// the undo policies the settings button steps through, in order
+fn next_undo_policy(policy: UndoPolicy) -> UndoPolicy {
+    match policy {
+        UndoPolicy::Free => UndoPolicy::Limited(3),
+        UndoPolicy::Limited(_) => UndoPolicy::Penalized(64),
+        UndoPolicy::Penalized(_) => UndoPolicy::Free,
+    }
+}
+
+/// Keys the board answers to, as (`KeyboardEvent.code`, what it does). The
+/// help overlay is drawn from this, so keep it in step with `key_action`.
+const KEY_BINDINGS: [(&str, &str); 9] = [
+    ("ArrowLeft", "move left"),
+    ("ArrowRight", "move right"),
+    ("ArrowUp", "move up"),
+    ("ArrowDown", "move down"),
+    ("KeyU", "undo"),
+    ("KeyN", "new game"),
+    ("KeyR", "restart this game"),
+    ("Slash", "show or hide this help"),
+    ("Escape", "hide this help"),
+];
+
+fn key_action(code: &str) -> Option<Action> {
+    match code {
+        "ArrowLeft" => Some(Direction::Left.into()),
+        "ArrowRight" => Some(Direction::Right.into()),
+        "ArrowDown" => Some(Direction::Down.into()),
+        "ArrowUp" => Some(Direction::Up.into()),
+        "KeyU" => Some(Action::Undo),
+        "KeyN" => Some(Action::NewGame),
+        "KeyR" => Some(Action::Restart),
+        "Slash" => Some(Action::ToggleHelp),
+        "Escape" => Some(Action::CloseHelp),
+        _ => None,
+    }
+}
+
+/// how a key from `KEY_BINDINGS` is written on the keyboard
+fn key_label(code: &str) -> &str {
+    match code {
+        "ArrowLeft" => "←",
+        "ArrowRight" => "→",
+        "ArrowUp" => "↑",
+        "ArrowDown" => "↓",
+        "Slash" => "?",
+        "Escape" => "Esc",
+        _ => code.strip_prefix("Key").unwrap_or(code),
+    }
+}
+
+fn describe_undo_policy(policy: UndoPolicy) -> String {
+    match policy {
+        UndoPolicy::Free => "free".into(),
+        UndoPolicy::Limited(n) => format!("{n} per game"),
+        UndoPolicy::Penalized(cost) => format!("costs {cost}"),
+    }
+}
+
+fn viewport() -> (f64, f64) {
+    let w = window().unwrap();
+    (
+        w.inner_width().unwrap().as_f64().unwrap(),
+        w.inner_height().unwrap().as_f64().unwrap(),
+    )
 }
 
 struct Model {
     stats: Stats,
-    prev: GameState,
     gs: GameState,
     container: NodeRef,
     scoreboard_dialog: NodeRef,
+    stats_io: NodeRef,
+    code_input: NodeRef,
+    theme_input: NodeRef,
+    /// Tracked here rather than read from the dialog in `view`: Escape closes
+    /// the dialog without any message, so reading `open()` rendered with
+    /// whatever the DOM said at the last unrelated update
+    scoreboard_open: bool,
+    /// the keyboard help overlay is showing
+    help_open: bool,
     touch_start: Option<(i32, i32)>,
+    board_size: BoardSize,
+    layout: Layout,
+    /// applied to the next new game, so it can't be changed mid-game
+    undo_policy: UndoPolicy,
+    /// also applied from the next new game
+    spawns_per_move: usize,
+    /// next new game is a zen one: never lost, with free undo
+    zen: bool,
+    /// cells the last move spawned into, animated in when `animate_spawns` is on
+    spawned: Vec<usize>,
+    animate_spawns: bool,
+    /// move being previewed while shift+arrow is held, when `ghosts` is on.
+    /// Only ever rendered from `GameState::preview_move`, never applied
+    ghost: Option<Direction>,
+    ghosts: bool,
+    /// record undos with each finished game, flagging it as assisted
+    count_undos: bool,
+    /// `None` for the stylesheet's own colors
+    theme: Option<Theme>,
+    /// mirror the board so the biggest tile always shows bottom right
+    pin_corner: bool,
+    timer: GameTimer,
+    /// every move of the current game, for the replay in the stats dialog
+    log: GameLog,
+    /// milestone tiles the last move reached for the first time
+    unlocked: Vec<u32>,
+    /// biggest tile the current game has had so far
+    best_tile: u32,
+    /// moves into `log` the replay shows, `None` to follow the live game
+    replay_step: Option<usize>,
+
+    storage: Box<dyn Storage>,
+    /// false when settings and the game only last until the page closes
+    persistent: bool,
 
     debug: String,
 }
@@ -56,21 +508,63 @@ struct Model {
 impl Model {
     const LS_KEY_GAME: &str = "game";
     const LS_KEY_HISTORY: &str = "history";
+    const LS_KEY_ACHIEVEMENTS: &str = "achievements";
+    const LS_KEY_BOARD_SIZE: &str = "board_size";
+    const LS_KEY_UNDO_POLICY: &str = "undo_policy";
+    const LS_KEY_SPAWNS_PER_MOVE: &str = "spawns_per_move";
+    const LS_KEY_ZEN: &str = "zen";
+    const LS_KEY_ANIMATE_SPAWNS: &str = "animate_spawns";
+    const LS_KEY_GHOSTS: &str = "ghosts";
+    const LS_KEY_COUNT_UNDOS: &str = "count_undos";
+    const LS_KEY_PIN_CORNER: &str = "pin_corner";
+    const LS_KEY_TIMER: &str = "timer";
+    const LS_KEY_LOG: &str = "log";
+    const LS_KEY_BEST_TILE: &str = "best_tile";
+    const LS_KEY_THEME: &str = "theme";
 
     fn save(&self) {
-        let storage = &window().unwrap().local_storage().unwrap().unwrap();
-        storage
-            .set_item(
-                Model::LS_KEY_GAME,
-                &serde_json::to_string(&self.gs).unwrap(),
-            )
-            .unwrap();
-        storage
-            .set_item(
-                Model::LS_KEY_HISTORY,
-                &serde_json::to_string(&self.stats.history).unwrap(),
-            )
-            .unwrap();
+        /// what `migrate_save` reads back for the current `SAVE_VERSION`
+        #[derive(Serialize)]
+        struct Save<'a> {
+            version: u64,
+            game: &'a GameState,
+        }
+
+        let storage = &*self.storage;
+        put(
+            storage,
+            Model::LS_KEY_GAME,
+            &Save {
+                version: SAVE_VERSION,
+                game: &self.gs,
+            },
+        );
+        put(storage, Model::LS_KEY_HISTORY, &self.stats.history);
+        put(
+            storage,
+            Model::LS_KEY_ACHIEVEMENTS,
+            &self.stats.achievements,
+        );
+        put(storage, Model::LS_KEY_BOARD_SIZE, &self.board_size);
+        put(storage, Model::LS_KEY_UNDO_POLICY, &self.undo_policy);
+        put(
+            storage,
+            Model::LS_KEY_SPAWNS_PER_MOVE,
+            &self.spawns_per_move,
+        );
+        put(storage, Model::LS_KEY_ZEN, &self.zen);
+        put(storage, Model::LS_KEY_ANIMATE_SPAWNS, &self.animate_spawns);
+        put(storage, Model::LS_KEY_PIN_CORNER, &self.pin_corner);
+        put(storage, Model::LS_KEY_GHOSTS, &self.ghosts);
+        put(storage, Model::LS_KEY_COUNT_UNDOS, &self.count_undos);
+        put(storage, Model::LS_KEY_LOG, &self.log);
+        put(storage, Model::LS_KEY_BEST_TILE, &self.best_tile);
+        put(storage, Model::LS_KEY_THEME, &self.theme);
+        put(
+            storage,
+            Model::LS_KEY_TIMER,
+            &self.timer.elapsed_ms(Date::now()),
+        );
     }
 
     fn scoreboard(&self) -> Html {
@@ -80,9 +574,13 @@ impl Model {
             .0
             .iter()
             .flatten()
-            .map(|(score, date)| {
+            .map(|(score, date, assisted)| {
+                let score = score.to_formatted_string(&Locale::en);
                 html! {
-                    <tr><td>{score.to_formatted_string(&Locale::en)}</td><td>{date}</td></tr>
+                    <tr>
+                        <td title={assisted.then_some("used undo")}>{ if *assisted { format!("{score}*") } else { score } }</td>
+                        <td>{date}</td>
+                    </tr>
                 }
             });
 
@@ -96,6 +594,79 @@ impl Model {
         }
     }
 
+    fn achievements(&self) -> Html {
+        let a = &self.stats.achievements;
+        let tiles = MILESTONE_TILES.iter().map(|&t| {
+            let reached = a.reached(t);
+            html! {
+                <li class={classes!((!reached).then_some("locked"))}>
+                    { format!("{} First {t}", if reached { "★" } else { "☆" }) }
+                </li>
+            }
+        });
+        html! {
+            <div class="achievements">
+                <h2>{ "Achievements" }</h2>
+                <ul>{ for tiles }</ul>
+                <div>{ "Total merges: " }{ a.total_merges().to_formatted_string(&Locale::en) }</div>
+                <div>{ "Longest game: " }{ a.longest_game().to_formatted_string(&Locale::en) }{ " moves" }</div>
+                <div>{ "Best single move: " }{ a.best_move_score().to_formatted_string(&Locale::en) }{ " points" }</div>
+            </div>
+        }
+    }
+
+    /// Every key from `KEY_BINDINGS` and the other ways to play. Not a
+    /// `<dialog>`, so it stays out of the way of the scoreboard's.
+    fn help(&self, link: &Scope<Model>) -> Html {
+        let rows = KEY_BINDINGS.iter().map(|(code, what)| {
+            html! { <tr><td class="key">{ key_label(code) }</td><td>{ *what }</td></tr> }
+        });
+        html! {
+            <div class="help_overlay" onclick={link.callback(|_| Action::CloseHelp)}>
+                <h2>{ "Controls" }</h2>
+                <table>
+                    { for rows }
+                    <tr><td class="key">{ "shift + arrow" }</td><td>{ "preview a move, with ghost preview on" }</td></tr>
+                    <tr><td class="key">{ "swipe" }</td><td>{ "move, on touch screens" }</td></tr>
+                </table>
+            </div>
+        }
+    }
+
+    /// Step through the current game. Boards are rebuilt from the log, so the
+    /// live game is never touched.
+    fn replay(&self, link: &Scope<Model>) -> Html {
+        let moves = self.log.move_count();
+        let step = self.replay_step.unwrap_or(moves).min(moves);
+        let board = self.log.board_after(step);
+        let oninput = link.callback(|e: InputEvent| {
+            Action::Scrub(
+                e.target_unchecked_into::<HtmlInputElement>()
+                    .value()
+                    .parse()
+                    .unwrap_or_default(),
+            )
+        });
+        html! {
+            <div>
+                <h2>{"Replay"}</h2>
+                <input type="range" min="0" max={moves.to_string()} value={step.to_string()} oninput={oninput} />
+                <div>{ format!("move {step} of {moves}") }</div>
+                <table class="replay">
+                    { for board.rows().iter().map(|r| html! {
+                        <tr>
+                            { for r.iter().map(|t| html! {
+                                <td class={t.map(|t| format!("value_{}", t.exponent())).unwrap_or("empty".into())}>
+                                    { for t.map(|t| t.as_u32()) }
+                                </td>
+                            })}
+                        </tr>
+                    })}
+                </table>
+            </div>
+        }
+    }
+
     fn histogram(&self) -> Html {
         let min = self
             .stats
@@ -165,6 +736,65 @@ impl Model {
         }
     }
 
+    /// Switch to `theme`, or after an error back to the default colors with
+    /// the error left in the theme box
+    fn set_theme(&mut self, theme: Result<impl Into<Option<Theme>>, String>) {
+        self.theme = match theme {
+            Ok(theme) => theme.into(),
+            Err(e) => {
+                if let Some(input) = self.theme_input.cast::<HtmlInputElement>() {
+                    input.set_value(&format!("could not load theme: {e}"));
+                }
+                None
+            }
+        };
+        apply_theme(self.theme.as_ref());
+        self.save();
+    }
+
+    /// record the current game in stats and replace it with `gs`, under the
+    /// rules picked for new games
+    fn start_game(&mut self, gs: GameState) {
+        self.stats.record(
+            &self.gs,
+            Date::new_0().to_date_string().as_string().unwrap(),
+            self.best_tile,
+            self.count_undos,
+        );
+        self.spawned.clear();
+        self.unlocked.clear();
+        self.gs = gs.with_rules(Rules {
+            // zen is for relaxing, so undo is never rationed there
+            undo_policy: if self.zen {
+                UndoPolicy::Free
+            } else {
+                self.undo_policy
+            },
+            spawns_per_move: self.spawns_per_move,
+            zen: self.zen,
+            ..Rules::default()
+        });
+        self.log = GameLog::new(&self.gs);
+        self.best_tile = self.gs.max();
+        self.restart_timer();
+        self.save();
+    }
+
+    fn view(&self) -> View {
+        if self.pin_corner {
+            View::pinning(&self.gs, Corner::BottomRight)
+        } else {
+            View::default()
+        }
+    }
+
+    fn restart_timer(&mut self) {
+        self.timer = GameTimer::default();
+        if !tab_hidden() {
+            self.timer.resume(Date::now());
+        }
+    }
+
     fn scoreboard_elem(&self) -> Option<HtmlDialogElement> {
         self.scoreboard_dialog.cast::<HtmlDialogElement>()
     }
@@ -174,17 +804,116 @@ impl Component for Model {
     type Message = Action;
     type Properties = ();
 
-    fn create(_ctx: &Context<Self>) -> Self {
-        let gs = load_from_storage(Model::LS_KEY_GAME).unwrap_or_else(GameState::new_from_entropy);
-        let stats = Stats::new(load_from_storage(Model::LS_KEY_HISTORY).unwrap_or_default());
+    fn create(ctx: &Context<Self>) -> Self {
+        // a corrupted save gets replaced rather than trusted
+        let (storage, persistent) = open_storage(window().unwrap().local_storage().ok().flatten());
+        let gs = match storage.get(Model::LS_KEY_GAME).map(|s| migrate_save(&s)) {
+            Some(Ok(gs)) => gs,
+            Some(Err(e)) => {
+                log::warn!("discarding saved game: {e}");
+                GameState::new_from_entropy()
+            }
+            None => GameState::new_from_entropy(),
+        };
+        let mut stats =
+            Stats::new(load_from_storage(&*storage, Model::LS_KEY_HISTORY).unwrap_or_default());
+        stats.achievements =
+            load_from_storage(&*storage, Model::LS_KEY_ACHIEVEMENTS).unwrap_or_default();
+        let board_size =
+            load_from_storage(&*storage, Model::LS_KEY_BOARD_SIZE).unwrap_or(BoardSize::Large);
+        let undo_policy =
+            load_from_storage(&*storage, Model::LS_KEY_UNDO_POLICY).unwrap_or_default();
+        let spawns_per_move =
+            load_from_storage(&*storage, Model::LS_KEY_SPAWNS_PER_MOVE).unwrap_or(1);
+        let zen = load_from_storage(&*storage, Model::LS_KEY_ZEN).unwrap_or(false);
+        let animate_spawns =
+            load_from_storage(&*storage, Model::LS_KEY_ANIMATE_SPAWNS).unwrap_or(true);
+        let ghosts = load_from_storage(&*storage, Model::LS_KEY_GHOSTS).unwrap_or(false);
+        let count_undos = load_from_storage(&*storage, Model::LS_KEY_COUNT_UNDOS).unwrap_or(false);
+        let pin_corner = load_from_storage(&*storage, Model::LS_KEY_PIN_CORNER).unwrap_or(false);
+        // the stored theme was checked when it was loaded, but storage can be edited
+        let theme = load_from_storage(&*storage, Model::LS_KEY_THEME)
+            .flatten()
+            .filter(|theme: &Theme| theme.validate().is_ok());
+        apply_theme(theme.as_ref());
+        // older saves have no log, and a log that doesn't lead to the saved
+        // board can't be replayed, so either way replays start from here
+        let log = load_from_storage(&*storage, Model::LS_KEY_LOG)
+            .filter(|log: &GameLog| log.reconstruct() == gs)
+            .unwrap_or_else(|| GameLog::new(&gs));
+        let best_tile = track_best_tile(
+            load_from_storage(&*storage, Model::LS_KEY_BEST_TILE).unwrap_or(0),
+            &gs,
+        );
+        let mut timer =
+            GameTimer::new(load_from_storage(&*storage, Model::LS_KEY_TIMER).unwrap_or(0.));
+        if !tab_hidden() {
+            timer.resume(Date::now());
+        }
+
+        let link = ctx.link().clone();
+        let on_resize = Closure::<dyn Fn()>::new(move || link.send_message(Action::Resize));
+        window()
+            .unwrap()
+            .add_event_listener_with_callback("resize", on_resize.as_ref().unchecked_ref())
+            .unwrap();
+        on_resize.forget(); // lives as long as the page
+
+        let link = ctx.link().clone();
+        let on_visibility =
+            Closure::<dyn Fn()>::new(move || link.send_message(Action::VisibilityChanged));
+        window()
+            .unwrap()
+            .document()
+            .unwrap()
+            .add_event_listener_with_callback(
+                "visibilitychange",
+                on_visibility.as_ref().unchecked_ref(),
+            )
+            .unwrap();
+        on_visibility.forget();
+
+        let link = ctx.link().clone();
+        let on_tick = Closure::<dyn Fn()>::new(move || link.send_message(Action::Tick));
+        window()
+            .unwrap()
+            .set_interval_with_callback_and_timeout_and_arguments_0(
+                on_tick.as_ref().unchecked_ref(),
+                1000,
+            )
+            .unwrap();
+        on_tick.forget();
 
         Self {
-            prev: gs.clone(),
             gs,
             stats,
             container: NodeRef::default(),
             scoreboard_dialog: NodeRef::default(),
+            stats_io: NodeRef::default(),
+            code_input: NodeRef::default(),
+            theme_input: NodeRef::default(),
+            scoreboard_open: false,
+            help_open: false,
             touch_start: None,
+            board_size,
+            layout: Layout::new(viewport(), board_size),
+            undo_policy,
+            spawns_per_move,
+            zen,
+            spawned: Vec::new(),
+            timer,
+            log,
+            best_tile,
+            unlocked: Vec::new(),
+            replay_step: None,
+            storage,
+            persistent,
+            animate_spawns,
+            ghost: None,
+            ghosts,
+            count_undos,
+            theme,
+            pin_corner,
             debug: String::new(),
         }
     }
@@ -192,20 +921,41 @@ impl Component for Model {
     fn update(&mut self, ctx: &Context<Self>, dir: Self::Message) -> bool {
         match dir {
             Action::Move(dir) => {
-                if self.gs.can_move(dir) {
-                    self.prev = self.gs.clone();
-                    self.gs.do_move(dir);
-                    self.gs.spawn_tile_with_dir(dir);
+                let dir = self.view().to_board(dir);
+                let before = self.gs.clone();
+                let Some(spawns) = self.log.apply_spawning(&mut self.gs, dir) else {
+                    return false;
+                };
+                self.unlocked = self.stats.achievements.record_move(&before, dir, &self.gs);
+                self.best_tile = track_best_tile(self.best_tile, &self.gs);
+                self.spawned = spawns.into_iter().map(|(index, _)| index).collect();
+                self.save();
+                true
+            }
+            Action::Undo => {
+                if self.gs.undo() {
+                    self.log.undo();
+                    self.spawned.clear();
+                    self.unlocked.clear();
                     self.save();
                     true
                 } else {
                     false
                 }
             }
-            Action::Undo => {
-                self.gs = self.prev.clone();
-                self.save();
-                true
+            // not recorded in stats: the game is being retried, not finished
+            Action::Restart => {
+                if self.gs.restart() {
+                    self.log = GameLog::new(&self.gs);
+                    self.best_tile = self.gs.max();
+                    self.spawned.clear();
+                    self.unlocked.clear();
+                    self.restart_timer();
+                    self.save();
+                    true
+                } else {
+                    false
+                }
             }
             Action::TouchStart(ts) => {
                 let tl = ts.touches();
@@ -220,14 +970,41 @@ impl Component for Model {
                 true
             }
             Action::NewGame => {
-                let score = self.gs.score();
-                if score > 10 {
-                    self.stats
-                        .on_game_finish(score, Date::new_0().to_date_string().as_string().unwrap());
+                self.start_game(GameState::new_from_entropy());
+                true
+            }
+            Action::PlayCode => {
+                let Some(input) = self.code_input.cast::<HtmlInputElement>() else {
+                    return false;
+                };
+                let code = input.value();
+                if code.trim().is_empty() {
+                    return false;
                 }
-                self.gs = GameState::new_from_entropy();
-                self.prev = self.gs.clone();
-                self.save();
+                self.start_game(GameState::new_from_code(&code));
+                input.blur().unwrap();
+                true
+            }
+            Action::LoadTheme => {
+                let Some(input) = self.theme_input.cast::<HtmlInputElement>() else {
+                    return false;
+                };
+                let text = input.value().trim().to_string();
+                if text.starts_with("https://") || text.starts_with("http://") {
+                    ctx.link()
+                        .send_future(async { Action::ThemeFetched(fetch_text(text).await) });
+                    false
+                } else {
+                    self.set_theme(Theme::parse(&text));
+                    true
+                }
+            }
+            Action::ThemeFetched(fetched) => {
+                self.set_theme(fetched.and_then(|json| Theme::parse(&json)));
+                true
+            }
+            Action::ResetTheme => {
+                self.set_theme(Ok(None));
                 true
             }
             Action::TouchMove(te) => {
@@ -240,7 +1017,8 @@ impl Component for Model {
                 let dx = t.client_x() - x;
                 let dy = t.client_y() - y;
 
-                if dx.abs() > 100 && dy.abs() < 50 {
+                let (along, across) = self.layout.swipe_thresholds();
+                if dx.abs() > along && dy.abs() < across {
                     ctx.link().send_message(Action::Move(if dx.is_negative() {
                         Direction::Left
                     } else {
@@ -248,7 +1026,7 @@ impl Component for Model {
                     }));
                     self.touch_start = None;
                 }
-                if dy.abs() > 100 && dx.abs() < 50 {
+                if dy.abs() > along && dx.abs() < across {
                     ctx.link().send_message(Action::Move(if dy.is_negative() {
                         Direction::Up
                     } else {
@@ -265,13 +1043,115 @@ impl Component for Model {
                 true
             }
             Action::OpenScoreboard => {
+                self.scoreboard_open = true;
+                self.help_open = false;
+                self.replay_step = None;
                 self.scoreboard_elem().unwrap().show_modal().unwrap();
                 true
             }
+            Action::ToggleHelp => {
+                self.help_open = !self.help_open;
+                true
+            }
+            Action::CloseHelp => std::mem::replace(&mut self.help_open, false),
+            // also sent by the dialog's close event, which fires for Escape and
+            // again after the Close button's own `close()`
             Action::CloseScoreboard => {
+                let was_open = std::mem::replace(&mut self.scoreboard_open, false);
                 self.scoreboard_elem().unwrap().close();
+                was_open
+            }
+            Action::CycleBoardSize => {
+                self.board_size = self.board_size.next();
+                self.layout = Layout::new(viewport(), self.board_size);
+                self.save();
+                true
+            }
+            Action::Ghost(dir) => {
+                let dir = dir.filter(|_| self.ghosts);
+                let changed = dir != self.ghost;
+                self.ghost = dir;
+                changed
+            }
+            Action::ToggleGhosts => {
+                self.ghosts = !self.ghosts;
+                self.ghost = None;
+                self.save();
+                true
+            }
+            Action::ToggleCountUndos => {
+                self.count_undos = !self.count_undos;
+                self.save();
+                true
+            }
+            Action::TogglePinCorner => {
+                self.pin_corner = !self.pin_corner;
+                self.save();
+                true
+            }
+            Action::ToggleSpawnAnimation => {
+                self.animate_spawns = !self.animate_spawns;
+                self.save();
+                true
+            }
+            Action::CycleUndoPolicy => {
+                self.undo_policy = next_undo_policy(self.undo_policy);
+                self.save();
+                true
+            }
+            Action::CycleSpawnsPerMove => {
+                self.spawns_per_move = self.spawns_per_move % 3 + 1;
+                self.save();
+                true
+            }
+            Action::ToggleZen => {
+                self.zen = !self.zen;
+                self.save();
+                true
+            }
+            Action::Scrub(step) => {
+                self.replay_step = Some(step);
+                true
+            }
+            Action::Tick => self.timer.running(),
+            Action::VisibilityChanged => {
+                if tab_hidden() {
+                    self.timer.pause(Date::now());
+                    self.save();
+                } else {
+                    self.timer.resume(Date::now());
+                }
+                false
+            }
+            Action::Resize => {
+                let layout = Layout::new(viewport(), self.board_size);
+                let changed = layout != self.layout;
+                self.layout = layout;
+                changed
+            }
+            Action::ExportStats => {
+                if let Some(io) = self.stats_io.cast::<HtmlTextAreaElement>() {
+                    io.set_value(&self.stats.export());
+                    io.select();
+                }
                 false
             }
+            Action::ImportStats => {
+                let Some(io) = self.stats_io.cast::<HtmlTextAreaElement>() else {
+                    return false;
+                };
+                match Stats::import(&io.value()) {
+                    Ok(stats) => {
+                        self.stats = stats;
+                        self.save();
+                        true
+                    }
+                    Err(e) => {
+                        io.set_value(&format!("could not import stats: {e}"));
+                        false
+                    }
+                }
+            }
         }
     }
 
@@ -279,48 +1159,126 @@ impl Component for Model {
         // This gives us a component's "`Scope`" which allows us to send messages, etc to the component.
         let link = ctx.link();
 
-        let r = self.gs.rows();
-        let rows = r.iter().map(|&r| {
+        // the ghost is drawn in the live board's view, so it doesn't jump
+        // around if the move would take the biggest tile elsewhere
+        let view = self.view();
+        let ghost = self
+            .ghost
+            .and_then(|d| self.gs.preview_move(view.to_board(d)));
+        let shown = ghost.as_ref().unwrap_or(&self.gs);
+        let animate = self.animate_spawns && ghost.is_none();
+        let r = view.show(shown.rows());
+        let rows = r.iter().enumerate().map(|(ri, &r)| {
             html! {
                 <tr>
-                    {for r.iter().map(|t| html! {
-                        <td>
-                            <div class={t.map(|t| format!("value_{}", t.exponent())).unwrap_or("empty".into())}>
-                                {if let Some(t) = t { html!{t.as_u32()} } else { "".into() }}
-                            </div>
-                        </td>
+                    {for r.iter().enumerate().map(|(ci, t)| {
+                        let (br, bc) = view.cell(ri, ci);
+                        let index = br * 4 + bc;
+                        let value = t.map(|t| format!("value_{}", t.exponent())).unwrap_or("empty".into());
+                        // the stylesheet's palette runs out, so bigger tiles bring their own color
+                        let (large, style) = match t {
+                            Some(t) if !t.has_palette_color() => (Some("value_large"), Some(format!("--tile-bg: var(--tile-{}, {})", t.exponent(), t.background_hex()))),
+                            _ => (None, None),
+                        };
+                        // keyed by move so the animation restarts even when the same cell spawns twice in a row
+                        let (class, key) = if animate && self.spawned.contains(&index) {
+                            (classes!(value, large, "spawned"), format!("spawn-{}", self.gs.move_count()))
+                        } else {
+                            (classes!(value, large), String::new())
+                        };
+                        html! {
+                            <td>
+                                <div key={key} class={class} style={style}>
+                                    {if let Some(t) = t { html!{t.as_u32()} } else { "".into() }}
+                                </div>
+                            </td>
+                        }
                     })}
                 </tr>
             }
         });
 
-        let onkeydown = link.batch_callback(|e: KeyboardEvent| match e.code().as_str() {
-            "ArrowLeft" => Some(Direction::Left.into()),
-            "ArrowRight" => Some(Direction::Right.into()),
-            "ArrowDown" => Some(Direction::Down.into()),
-            "ArrowUp" => Some(Direction::Up.into()),
-            "KeyU" => Some(Action::Undo),
-            "KeyN" => Some(Action::NewGame),
+        let onkeydown = link.batch_callback(|e: KeyboardEvent| {
+            // typing a code isn't playing
+            if e.target_dyn_into::<HtmlInputElement>().is_some() {
+                return None;
+            }
+            // holding a key fires keydown over and over, only act on the initial press
+            if e.repeat() {
+                return None;
+            }
+            let arrow = match e.code().as_str() {
+                "ArrowLeft" => Some(Direction::Left),
+                "ArrowRight" => Some(Direction::Right),
+                "ArrowDown" => Some(Direction::Down),
+                "ArrowUp" => Some(Direction::Up),
+                _ => None,
+            };
+            // "?" is shift+slash
+            if e.shift_key() && e.code() != "Slash" {
+                return arrow.map(|d| Action::Ghost(Some(d)));
+            }
+            key_action(&e.code())
+        });
+
+        // letting go of shift or the arrow puts the real board back
+        let onkeyup = link.batch_callback(|e: KeyboardEvent| match e.code().as_str() {
+            "ShiftLeft" | "ShiftRight" | "ArrowLeft" | "ArrowRight" | "ArrowDown" | "ArrowUp" => {
+                Some(Action::Ghost(None))
+            }
             _ => None,
         });
+        let onblur = link.callback(|_: FocusEvent| Action::Ghost(None));
 
         let ontouchstart = link.callback(|e: TouchEvent| Action::TouchStart(e));
         let ontouchend = link.callback(|_e: TouchEvent| Action::TouchEnd);
         let ontouchmove = link.callback(|e: TouchEvent| Action::TouchMove(e));
 
+        // worked out from the board on every render rather than kept as
+        // flags, so undoing out of a lost or won position clears the banners
         let lost = self.gs.lost();
-        let score = self.gs.score();
+        let won = self.gs.rules().win_stops_game && self.gs.won();
+        let moves_remaining = self.gs.moves_remaining();
+        let forced = self.gs.only_move();
+        let near_loss = self.gs.is_near_loss();
+        // only worth cheering for when it beats everything on the board
+        let record_merge = self
+            .gs
+            .largest_merge_result()
+            .filter(|&(_, t)| t > self.gs.max());
+        let score = self.gs.effective_score();
+        let undo_status = match self.gs.rules().undo_policy {
+            UndoPolicy::Free => None,
+            UndoPolicy::Limited(_) => Some(format!(
+                " | Undos left: {}",
+                self.gs.undos_remaining().unwrap()
+            )),
+            UndoPolicy::Penalized(cost) => Some(format!(
+                " | Undo penalty: {} ({cost} each)",
+                self.gs.undo_penalty()
+            )),
+        };
 
-        let stats_contents = if self.scoreboard_elem().map(|d| d.open()).unwrap_or(false) {
+        let stats_contents = if self.scoreboard_open {
             let scoreboard = self.scoreboard();
             let hist = self.histogram();
             html! {
                 <div>
                     <div>
-                        { "Lifetime points: " } { (self.stats.lifetime_points + score).to_formatted_string(&Locale::en) }
+                        { "Lifetime points: " } { self.stats.lifetime_points.saturating_add(score).to_formatted_string(&Locale::en) }
+                    </div>
+                    <div>
+                        { "Best tile ever: " } { self.stats.best_tile().max(self.best_tile) }
                     </div>
                     { scoreboard }
+                    { self.achievements() }
                     { hist }
+                    { self.replay(link) }
+                    <div>
+                        <textarea ref={self.stats_io.clone()} placeholder="paste exported stats here" />
+                    </div>
+                    <button onclick={link.callback(|_| Action::ExportStats)}>{ "Export" }</button>
+                    <button onclick={link.callback(|_| Action::ImportStats)}>{ "Import" }</button>
                     <button autofocus=true onclick={link.callback(|_| Action::CloseScoreboard)}>{ "Close" }</button>
                 </div>
             }
@@ -329,20 +1287,49 @@ impl Component for Model {
         };
 
         html! {
-            <div ref={self.container.clone()} class="container" tabindex="0" onkeydown={onkeydown} ontouchstart={ontouchstart} ontouchend={ontouchend} ontouchmove={ontouchmove}>
+            <div ref={self.container.clone()} class="container" style={self.layout.css()} tabindex="0" onkeydown={onkeydown} onkeyup={onkeyup} onblur={onblur} ontouchstart={ontouchstart} ontouchend={ontouchend} ontouchmove={ontouchmove}>
                 <div class="game">
-                    <table>
+                    <table class={classes!(ghost.is_some().then_some("ghost"))}>
                         { for rows }
                     </table>
                     { if lost { html! { <span class="lost_banner">{ "you lost" }</span> } } else { "".into() } }
+                    { if won { html! { <span class="lost_banner">{ "you won" }</span> } } else { "".into() } }
+                    { if moves_remaining == Some(0) && !lost && !won { html! { <span class="lost_banner">{ "out of moves" }</span> } } else { "".into() } }
                 </div>
+                { if self.help_open { self.help(link) } else { "".into() } }
+                { if !self.persistent { html! { <div class="storage_warning">{ "Storage is unavailable, so progress won't be saved" }</div> } } else { "".into() } }
                 <div class="score">
                     { "Score: " } { score.to_formatted_string(&Locale::en) }
+                    { " | Time: " } { format_duration(self.timer.elapsed_ms(Date::now())) }
+                    { for moves_remaining.map(|m| format!(" | Moves left: {m}")) }
+                    { for undo_status }
+                    { for forced.map(|d| format!(" | Forced move: {d:?}")) }
+                    { if near_loss { " | Almost out of moves" } else { "" } }
+                    { for record_merge.map(|(d, t)| format!(" | {d:?} makes a {t}!")) }
+                    { for self.unlocked.iter().map(|t| format!(" | First {t}!")) }
+                </div>
+                <div class="fill" title="board fill">
+                    <div class="fill-bar" style={format!("width: {}%", self.gs.fill_fraction() * 100.)} />
                 </div>
                 <button onclick={link.callback(|_| Action::Undo)}>{ "Undo (u)" }</button>
                 <button onclick={link.callback(|_| Action::NewGame)}>{ "New Game (n)" }</button>
+                <button disabled={self.gs.seed().is_none()} onclick={link.callback(|_| Action::Restart)}>{ "Restart this game (r)" }</button>
+                <input ref={self.code_input.clone()} type="text" placeholder="game code" onkeydown={link.batch_callback(|e: KeyboardEvent| (e.key() == "Enter").then_some(Action::PlayCode))} />
+                <button title="everyone who enters the same code gets the same game" onclick={link.callback(|_| Action::PlayCode)}>{ "Play code" }</button>
+                <input ref={self.theme_input.clone()} type="text" placeholder="theme JSON or URL" onkeydown={link.batch_callback(|e: KeyboardEvent| (e.key() == "Enter").then_some(Action::LoadTheme))} />
+                <button onclick={link.callback(|_| Action::LoadTheme)}>{ "Load theme" }</button>
+                <button disabled={self.theme.is_none()} onclick={link.callback(|_| Action::ResetTheme)}>{ "Default theme" }</button>
                 <button onclick={link.callback(|_| Action::OpenScoreboard)}>{ "Stats..." }</button>
-                <dialog ref={self.scoreboard_dialog.clone()} class="scoreboard">
+                <button onclick={link.callback(|_| Action::ToggleHelp)}>{ "Keys (?)" }</button>
+                <button onclick={link.callback(|_| Action::CycleBoardSize)}>{ format!("Size: {:?}", self.board_size) }</button>
+                <button title="hold shift and an arrow to preview a move" onclick={link.callback(|_| Action::ToggleGhosts)}>{ if self.ghosts { "Ghost preview: on" } else { "Ghost preview: off" } }</button>
+                <button title="games finished after an undo get an asterisk on the scoreboard" onclick={link.callback(|_| Action::ToggleCountUndos)}>{ if self.count_undos { "Count undos: on" } else { "Count undos: off" } }</button>
+                <button title="mirrors the picture only, arrows still move tiles the way they look" onclick={link.callback(|_| Action::TogglePinCorner)}>{ if self.pin_corner { "Big tile: bottom right" } else { "Big tile: anywhere" } }</button>
+                <button onclick={link.callback(|_| Action::ToggleSpawnAnimation)}>{ if self.animate_spawns { "Animations: on" } else { "Animations: off" } }</button>
+                <button title="takes effect from the next new game" onclick={link.callback(|_| Action::CycleUndoPolicy)}>{ format!("Undo: {}", describe_undo_policy(self.undo_policy)) }</button>
+                <button title="takes effect from the next new game" onclick={link.callback(|_| Action::CycleSpawnsPerMove)}>{ format!("Spawns per move: {}", self.spawns_per_move) }</button>
+                <button title="from the next new game: stuck boards lose their smallest tiles instead of ending, undo is free and games aren't scored" onclick={link.callback(|_| Action::ToggleZen)}>{ if self.zen { "Zen: on" } else { "Zen: off" } }</button>
+                <dialog ref={self.scoreboard_dialog.clone()} class="scoreboard" onclose={link.callback(|_: Event| Action::CloseScoreboard)}>
                     { stats_contents }
                 </dialog>
                 <span>{self.debug.clone()}</span>
@@ -351,6 +1338,17 @@ impl Component for Model {
     }
 
     fn rendered(&mut self, _ctx: &Context<Self>, _first_render: bool) {
+        // the timer re-renders every second, which mustn't pull focus out of
+        // the code box mid-typing
+        let typing = window()
+            .unwrap()
+            .document()
+            .unwrap()
+            .active_element()
+            .is_some_and(|e| e.is_instance_of::<HtmlInputElement>());
+        if typing {
+            return;
+        }
         self.container
             .cast::<HtmlElement>()
             .unwrap()
@@ -359,45 +1357,159 @@ impl Component for Model {
     }
 }
 
-fn load_from_storage<T: DeserializeOwned>(key: &str) -> Option<T> {
-    if let Ok(Some(t)) = window()
-        .unwrap()
-        .local_storage()
-        .unwrap()
-        .unwrap()
-        .get_item(key)
-    {
-        serde_json::from_str(&t).ok()
-    } else {
-        None
+/// Somewhere to keep the game and settings between visits
+trait Storage {
+    fn get(&self, key: &str) -> Option<String>;
+    fn set(&self, key: &str, value: &str);
+}
+
+impl Storage for web_sys::Storage {
+    fn get(&self, key: &str) -> Option<String> {
+        self.get_item(key).ok().flatten()
+    }
+
+    fn set(&self, key: &str, value: &str) {
+        // a full quota loses this save, not the game in progress
+        if let Err(e) = self.set_item(key, value) {
+            log::warn!("couldn't save {key}: {e:?}");
+        }
+    }
+}
+
+/// Stand-in for when the browser won't give us localStorage, like Safari's
+/// private mode. Everything still works for the life of the page.
+#[derive(Default)]
+struct MemoryStorage(RefCell<HashMap<String, String>>);
+
+impl Storage for MemoryStorage {
+    fn get(&self, key: &str) -> Option<String> {
+        self.0.borrow().get(key).cloned()
+    }
+
+    fn set(&self, key: &str, value: &str) {
+        self.0.borrow_mut().insert(key.into(), value.into());
+    }
+}
+
+/// `local`, if the browser gave us one, else memory. Also says whether
+/// what's stored will outlive the page.
+fn open_storage(local: Option<web_sys::Storage>) -> (Box<dyn Storage>, bool) {
+    match local {
+        Some(local) => (Box::new(local), true),
+        None => {
+            log::warn!("localStorage is unavailable, nothing will be saved");
+            (Box::new(MemoryStorage::default()), false)
+        }
+    }
+}
+
+fn load_from_storage<T: DeserializeOwned>(storage: &dyn Storage, key: &str) -> Option<T> {
+    serde_json::from_str(&storage.get(key)?).ok()
+}
+
+/// Store `value` as JSON under `key`. A value that won't serialize is
+/// logged and skipped, leaving whatever was saved there before.
+fn put<T: Serialize>(storage: &dyn Storage, key: &str, value: &T) {
+    match serde_json::to_string(value) {
+        Ok(json) => storage.set(key, &json),
+        Err(e) => log::warn!("couldn't serialize {key}: {e}"),
     }
 }
 
 impl Stats {
     fn new(history: StatsHistory) -> Stats {
-        let lifetime_points: u64 = history.0.iter().map(|h| h.score).sum();
+        let lifetime_points = history
+            .0
+            .iter()
+            .fold(0u64, |sum, h| sum.saturating_add(h.score));
         let mut scoreboard = Scoreboard::default();
         for g in &history.0 {
-            scoreboard.add(g.score, g.date.clone());
+            scoreboard.add(g.score, g.date.clone(), g.assisted());
         }
         Self {
             history,
             scoreboard,
             lifetime_points,
+            achievements: Achievements::default(),
+        }
+    }
+
+    /// every stat as one JSON blob, for backups
+    fn export(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    /// Replace stats from an `export` blob. Only the history is trusted, the
+    /// scoreboard and lifetime points are rebuilt from it so they can't disagree.
+    fn import(json: &str) -> Result<Stats, serde_json::Error> {
+        let imported: Stats = serde_json::from_str(json)?;
+        let mut stats = Stats::new(imported.history);
+        stats.achievements = imported.achievements;
+        Ok(stats)
+    }
+
+    /// Record a game that's being left, at its score after undo penalties,
+    /// and with its undos if `count_undos`. Games that barely started aren't
+    /// worth keeping, and zen games, which can't end, aren't scored.
+    fn record(&mut self, gs: &GameState, date: String, best_tile: u32, count_undos: bool) {
+        if gs.rules().zen {
+            return;
+        }
+        let score = gs.effective_score();
+        let undos_used = if count_undos {
+            gs.undos_used().try_into().unwrap_or(u32::MAX)
+        } else {
+            0
+        };
+        if score > 10 {
+            self.on_game_finish(score, date, gs.fingerprint(), best_tile, undos_used);
+        }
+    }
+
+    fn on_game_finish(
+        &mut self,
+        score: u64,
+        date: String,
+        id: u64,
+        best_tile: u32,
+        undos_used: u32,
+    ) {
+        if id != 0 && self.history.0.iter().any(|g| g.id == id) {
+            return; // already recorded this exact game
         }
+        let game = PastGameDatapoint {
+            score,
+            date,
+            id,
+            best_tile,
+            undos_used,
+        };
+        self.scoreboard
+            .add(score, game.date.clone(), game.assisted());
+        self.history.0.push(game);
+        self.lifetime_points = self.lifetime_points.saturating_add(score);
     }
 
-    fn on_game_finish(&mut self, score: u64, date: String) {
-        self.scoreboard.add(score, date.clone());
-        self.history.0.push(PastGameDatapoint { score, date });
-        self.lifetime_points += score;
+    fn best_tile(&self) -> u32 {
+        self.history
+            .0
+            .iter()
+            .map(|g| g.best_tile)
+            .max()
+            .unwrap_or(0)
     }
 }
 
+/// `best` after a move left the board as `gs`. Undo can shrink the board's
+/// biggest tile, but what was reached still counts.
+fn track_best_tile(best: u32, gs: &GameState) -> u32 {
+    best.max(gs.max())
+}
+
 impl Scoreboard {
-    fn add(&mut self, new_score: u64, date: String) {
+    fn add(&mut self, new_score: u64, date: String, assisted: bool) {
         for i in 0..self.0.len() {
-            if let Some((score, _)) = self.0[i] {
+            if let Some((score, _, _)) = self.0[i] {
                 if new_score <= score {
                     continue;
                 }
@@ -405,7 +1517,7 @@ impl Scoreboard {
 
             // this is a new high score, shift down and insert
             self.0[i..].rotate_right(1);
-            self.0[i] = Some((new_score, date));
+            self.0[i] = Some((new_score, date, assisted));
 
             return;
         }
@@ -416,3 +1528,283 @@ fn main() {
     wasm_logger::init(wasm_logger::Config::default());
     yew::Renderer::<Model>::new().render();
 }
+
+#[cfg(test)]
+mod test {
+    use twenty_48::{heuristics::Corner, Direction, GameState, Rules, UndoPolicy};
+
+    use crate::{
+        format_duration, key_action, key_label, load_from_storage, migrate_save, open_storage, put,
+        track_best_tile, BoardSize, GameTimer, Layout, Stats, StatsHistory, Theme, View,
+        KEY_BINDINGS, SAVE_VERSION,
+    };
+
+    #[test]
+    fn themes_are_checked() {
+        let theme = Theme::parse(
+            r##"{"background": "#4d4d4d", "text": "#fff", "tiles": {"1": "#14406b", "11": "#D4AF37"}}"##,
+        )
+        .unwrap();
+        assert_eq!(
+            theme.css(),
+            "--theme-background: #4d4d4d; --theme-text: #fff; --tile-1: #14406b; --tile-11: #D4AF37;"
+        );
+        let stored = serde_json::to_string(&Some(&theme)).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Option<Theme>>(&stored).unwrap(),
+            Some(theme)
+        );
+
+        for bad in [
+            "not json",
+            r##"{"background": "#000", "text": "#fff"}"##,
+            r##"{"background": "#000", "text": "#fff", "tiles": {}, "font": "serif"}"##,
+            r##"{"background": "red", "text": "#fff", "tiles": {}}"##,
+            r##"{"background": "#000", "text": "#fff", "tiles": {"1": "#000; color: red"}}"##,
+            r##"{"background": "#000", "text": "#fff", "tiles": {"40": "#000"}}"##,
+        ] {
+            assert!(Theme::parse(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn help_lists_working_keys() {
+        for (code, _) in KEY_BINDINGS {
+            assert!(key_action(code).is_some(), "{code}");
+        }
+        assert!(key_action("KeyZ").is_none());
+        assert_eq!(key_label("KeyU"), "U");
+        assert_eq!(key_label("Slash"), "?");
+
+        let mut labels: Vec<_> = KEY_BINDINGS
+            .iter()
+            .map(|(code, _)| key_label(code))
+            .collect();
+        labels.sort();
+        labels.dedup();
+        assert_eq!(labels.len(), KEY_BINDINGS.len());
+    }
+
+    #[test]
+    fn stats_round_trip() {
+        let mut stats = Stats::new(StatsHistory::default());
+        stats.on_game_finish(1200, "Mon Jan 01 2024".into(), 1, 0, 0);
+        stats.on_game_finish(800, "Tue Jan 02 2024".into(), 2, 0, 0);
+        let gs = GameState::from_list([
+            256, 256, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1,
+        ]);
+        let after = gs.simulate(Direction::Left).unwrap();
+        stats.achievements.record_move(&gs, Direction::Left, &after);
+
+        let imported = Stats::import(&stats.export()).unwrap();
+        assert_eq!(imported, stats);
+        assert!(imported.achievements.reached(512));
+        assert_eq!(imported.lifetime_points, 2000);
+        assert!(Stats::import("{\"history\": 5}").is_err());
+    }
+
+    #[test]
+    fn unavailable_storage_falls_back_to_memory() {
+        let (storage, persistent) = open_storage(None);
+        assert!(!persistent);
+        assert_eq!(load_from_storage::<bool>(&*storage, "ghosts"), None);
+
+        storage.set("ghosts", "true");
+        storage.set("board_size", "\"Small\"");
+        assert_eq!(load_from_storage(&*storage, "ghosts"), Some(true));
+        assert_eq!(
+            load_from_storage(&*storage, "board_size"),
+            Some(BoardSize::Small)
+        );
+        assert_eq!(load_from_storage::<bool>(&*storage, "board_size"), None);
+
+        put(&*storage, "ghosts", &false);
+        assert_eq!(load_from_storage(&*storage, "ghosts"), Some(false));
+        // JSON object keys have to be strings, so this can't be saved
+        let unsaveable = std::collections::BTreeMap::from([((1, 2), 3)]);
+        put(&*storage, "ghosts", &unsaveable);
+        assert_eq!(load_from_storage(&*storage, "ghosts"), Some(false));
+    }
+
+    #[test]
+    fn saves_migrate_or_are_rejected() {
+        let gs = GameState::new_from_seed(5);
+
+        let current = serde_json::json!({ "version": SAVE_VERSION, "game": &gs }).to_string();
+        assert_eq!(migrate_save(&current).unwrap(), gs);
+
+        let old = serde_json::to_string(&gs).unwrap();
+        assert_eq!(migrate_save(&old).unwrap(), gs);
+
+        assert!(migrate_save("{\"version\": 1, \"game\": [1, 2]}").is_err());
+        assert!(migrate_save("{\"version\": 99, \"game\": {}}").is_err());
+        assert!(migrate_save("not json").is_err());
+
+        // parses, but holds a tile no game could reach
+        let mut corrupt: serde_json::Value = serde_json::from_str(&old).unwrap();
+        corrupt["nums"][0] = 40.into();
+        assert!(migrate_save(&corrupt.to_string()).is_err());
+    }
+
+    #[test]
+    fn timer_counts_only_visible_stretches() {
+        let mut timer = GameTimer::new(500.);
+        timer.resume(1000.);
+        // a second visible event mid-stretch mustn't restart the stretch
+        timer.resume(1500.);
+        assert_eq!(timer.elapsed_ms(2000.), 1500.);
+        timer.pause(2000.);
+        timer.pause(2100.);
+        assert_eq!(timer.elapsed_ms(9000.), 1500.);
+        assert!(!timer.running());
+
+        timer.resume(10_000.);
+        timer.pause(9_000.);
+        assert_eq!(timer.elapsed_ms(20_000.), 1500.);
+
+        assert_eq!(format_duration(65_400.), "1:05");
+        assert_eq!(format_duration(3_725_000.), "1:02:05");
+    }
+
+    #[test]
+    fn duplicate_game_recorded_once() {
+        let mut stats = Stats::new(StatsHistory::default());
+        stats.on_game_finish(1200, "Mon Jan 01 2024".into(), 42, 0, 0);
+        stats.on_game_finish(1200, "Mon Jan 01 2024".into(), 42, 0, 0);
+        assert_eq!(stats.history.0.len(), 1);
+        assert_eq!(stats.lifetime_points, 1200);
+
+        // games from before ids were stored are never treated as duplicates
+        let old: StatsHistory =
+            serde_json::from_str(r#"[{"date": "Mon Jan 01 2024", "score": 100}]"#).unwrap();
+        let mut stats = Stats::new(old);
+        stats.on_game_finish(100, "Mon Jan 01 2024".into(), 0, 0, 0);
+        assert_eq!(stats.history.0.len(), 2);
+    }
+
+    #[test]
+    fn pinned_view_only_mirrors() {
+        let values = |rows: [[Option<twenty_48::Tile>; 4]; 4]| {
+            rows.map(|row| row.map(|t| t.map_or(-1, |t| t.as_u32() as i32)))
+        };
+        let gs = GameState::from_list([64, 2, -1, 4, 2, -1, 8, -1, -1, 4, -1, -1, 2, -1, -1, 2]);
+        let view = View::pinning(&gs, Corner::BottomRight);
+        let shown = view.show(gs.rows());
+        assert_eq!(shown[3][3], gs.rows()[0][0]);
+
+        let mut before: Vec<_> = gs.rows().concat();
+        let mut after = shown.concat();
+        before.sort();
+        after.sort();
+        assert_eq!(before, after);
+
+        // moving the picture is the same as moving the board and then drawing it
+        for d in Direction::ALL {
+            let mut on_screen = GameState::from_rows(values(shown));
+            on_screen.do_move(d);
+            let mut real = gs.clone();
+            real.do_move(view.to_board(d));
+            assert_eq!(
+                values(on_screen.rows()),
+                values(view.show(real.rows())),
+                "{d:?}"
+            );
+        }
+        assert_eq!(View::pinning(&gs, Corner::TopLeft), View::default());
+    }
+
+    #[test]
+    fn best_tile_never_drops() {
+        let mut gs = GameState::new_from_seed(9);
+        let mut best = track_best_tile(0, &gs);
+        for d in [Direction::Left, Direction::Down, Direction::Right].repeat(30) {
+            gs.apply(d);
+            // undo every other turn so the board's max sometimes goes back down
+            if gs.move_count() % 2 == 0 {
+                gs.undo();
+            }
+            let next = track_best_tile(best, &gs);
+            assert!(next >= best && next >= gs.max());
+            best = next;
+        }
+
+        let mut stats = Stats::new(StatsHistory::default());
+        stats.on_game_finish(500, "Mon Jan 01 2024".into(), 1, 64, 0);
+        stats.on_game_finish(400, "Tue Jan 02 2024".into(), 2, 128, 0);
+        assert_eq!(stats.best_tile(), 128);
+    }
+
+    #[test]
+    fn layout_scales_with_viewport_and_size() {
+        let small = Layout::new((800., 1000.), BoardSize::Small);
+        let large = Layout::new((800., 1000.), BoardSize::Large);
+        assert!(small.tile < large.tile);
+        assert_eq!(large, Layout::new((1600., 1000.), BoardSize::Large));
+
+        let (along, across) = large.swipe_thresholds();
+        assert!(along > across && across > 0);
+        assert!(small.swipe_thresholds().0 < along);
+    }
+
+    #[test]
+    fn counted_undos_mark_games_assisted() {
+        let mut gs = GameState::from_list_seeded(
+            [2, 2, 4, -1, 32, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+            6,
+        );
+        for _ in 0..2 {
+            gs.apply(Direction::Left);
+            gs.undo();
+        }
+        gs.apply(Direction::Left);
+        assert_eq!(gs.undos_used(), 2);
+
+        let mut stats = Stats::new(StatsHistory::default());
+        stats.record(&gs, "Mon Jan 01 2024".into(), 32, true);
+        let game = &stats.history.0[0];
+        assert_eq!(game.undos_used, 2);
+        assert!(game.assisted());
+        assert!(stats.scoreboard.0[0].as_ref().unwrap().2);
+
+        // the flag comes back when the scoreboard is rebuilt from history
+        let imported = Stats::import(&stats.export()).unwrap();
+        assert_eq!(imported, stats);
+
+        // with counting off the same game goes down unassisted
+        let mut stats = Stats::new(StatsHistory::default());
+        stats.record(&gs, "Mon Jan 01 2024".into(), 32, false);
+        assert!(!stats.history.0[0].assisted());
+        assert!(!stats.scoreboard.0[0].as_ref().unwrap().2);
+    }
+
+    #[test]
+    fn zen_games_are_not_scored() {
+        let gs = GameState::from_list([
+            512, 2, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1,
+        ])
+        .with_rules(Rules {
+            zen: true,
+            ..Rules::default()
+        });
+        let mut stats = Stats::new(StatsHistory::default());
+        stats.record(&gs, "Mon Jan 01 2024".into(), 512, false);
+        assert!(stats.history.0.is_empty());
+    }
+
+    #[test]
+    fn penalized_undo_lowers_recorded_score() {
+        let mut gs =
+            GameState::from_list([2, 2, 2, 2, 32, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1])
+                .with_rules(Rules {
+                    undo_policy: UndoPolicy::Penalized(16),
+                    ..Rules::default()
+                });
+        gs.apply(twenty_48::Direction::Left);
+        gs.undo();
+        gs.redo();
+
+        let mut stats = Stats::new(StatsHistory::default());
+        stats.record(&gs, "Mon Jan 01 2024".into(), 32, false);
+        assert_eq!(stats.history.0[0].score, gs.score() - 16);
+    }
+}